@@ -0,0 +1,34 @@
+#!/usr/bin/env bash
+# Times extracting one zip with --extract-jobs 1, 2, 4, and 8, for choosing its default.
+#
+# usage: scripts/bench-extract.sh ARCHIVE.zip [RUNS]
+#
+# Prints the machine's core count, the filesystem extracted to, and the archive size, then
+# the wall time (and peak memory, where GNU time is installed) of each run. Build with
+# `cargo build --release` first.
+set -euo pipefail
+
+archive=${1:?usage: $0 ARCHIVE.zip [RUNS]}
+runs=${2:-3}
+binary=${BINARY:-target/release/llm-context-builder}
+scratch=${TMPDIR:-/tmp}
+
+cores=$(nproc 2>/dev/null || sysctl -n hw.ncpu)
+echo "cores: $cores"
+echo "filesystem: $(df -hP "$scratch" | awk 'NR == 2 { print $1 " mounted on " $6 }')"
+echo "archive: $(du -h "$archive" | cut -f1) in $(unzip -Z1 "$archive" | wc -l | tr -d ' ') entries"
+
+for jobs in 1 2 4 8; do
+    for run in $(seq "$runs"); do
+        # A pattern nothing matches keeps the run down to extracting and walking
+        args=(--archive "$archive" --extract-jobs "$jobs" --include 'no-such-file' --dry-run)
+        if /usr/bin/time -f '' true 2>/dev/null; then
+            /usr/bin/time -f "jobs=$jobs run=$run %es %MKB" "$binary" "${args[@]}" \
+                >/dev/null 2>"$scratch/bench-extract.log" || true
+            tail -n 1 "$scratch/bench-extract.log"
+        else
+            TIMEFORMAT="jobs=$jobs run=$run %Rs"
+            time { "$binary" "${args[@]}" >/dev/null 2>&1 || true; }
+        fi
+    done
+done
@@ -0,0 +1,163 @@
+use log::warn;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+use crate::error::ContextError;
+use crate::search::{log_skip, FileMatcher, MatchedFile, SearchOptions, SkipReason};
+
+/// Extensions of archives `--descend-archives` opens.
+pub(crate) const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "jar", "war", "ear"];
+
+/// Archives nested deeper than this (an archive in an archive in an archive) are left closed.
+pub(crate) const MAX_ARCHIVE_DEPTH: usize = 3;
+
+/// Stop extracting from one top-level archive after this many bytes, so a zip bomb can't
+/// fill the disk.
+pub(crate) const MAX_ARCHIVE_BYTES: u64 = 256 * 1024 * 1024;
+
+pub(crate) fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ARCHIVE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Entries that matched inside one top-level archive, extracted to a shared temporary
+/// directory that lives as long as any of the returned files.
+struct Descent<'a> {
+    matcher: &'a FileMatcher,
+    options: &'a SearchOptions,
+    extraction: Arc<TempDir>,
+    extracted_bytes: u64,
+    matched: Vec<MatchedFile>,
+}
+
+/// Opens the archive at `path` and returns the entries that pass the same filters as files on
+/// disk, named like `vendor/lib.jar!com/example/Main.java`.
+pub(crate) fn descend_archive(
+    path: &Path,
+    display_path: &Path,
+    matcher: &FileMatcher,
+    options: &SearchOptions,
+) -> Result<Vec<MatchedFile>, ContextError> {
+    let mut descent = Descent {
+        matcher,
+        options,
+        extraction: Arc::new(tempfile::Builder::new().prefix("llm-context-").tempdir()?),
+        extracted_bytes: 0,
+        matched: Vec::new(),
+    };
+    descent.visit(File::open(path)?, &display_path.display().to_string(), 1)?;
+    Ok(descent.matched)
+}
+
+impl Descent<'_> {
+    fn visit(
+        &mut self,
+        reader: impl Read + Seek,
+        archive_name: &str,
+        depth: usize,
+    ) -> Result<(), ContextError> {
+        let corrupt = |e: zip::result::ZipError| ContextError::Extraction(e.into());
+        let mut archive = ZipArchive::new(reader).map_err(corrupt)?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).map_err(corrupt)?;
+            if entry.is_dir() {
+                continue;
+            }
+            // Entries that would escape the archive are never written, only skipped
+            let Some(inner_path) = entry.enclosed_name() else {
+                warn!(
+                    "Skipping unsafe entry '{}' in {}",
+                    entry.name(),
+                    archive_name
+                );
+                continue;
+            };
+            let display_name = format!("{}!{}", archive_name, inner_path.display());
+            if inner_path
+                .parent()
+                .is_some_and(|parent| self.in_ignored_dir(parent))
+            {
+                continue;
+            }
+
+            if is_archive(&inner_path) {
+                if depth >= MAX_ARCHIVE_DEPTH {
+                    warn!(
+                        "Not opening {}: archives nested more than {} deep",
+                        display_name, MAX_ARCHIVE_DEPTH
+                    );
+                } else if self.reserve(entry.size(), &display_name) {
+                    let size = entry.size();
+                    let mut nested = Vec::new();
+                    entry.by_ref().take(size).read_to_end(&mut nested)?;
+                    if let Err(e) = self.visit(Cursor::new(nested), &display_name, depth + 1) {
+                        warn!("Could not search {}: {}", display_name, e);
+                    }
+                }
+                continue;
+            }
+
+            if let Err(reason) = self.matcher.check(&inner_path) {
+                log_skip(Path::new(&display_name), &reason);
+                continue;
+            }
+            let size = entry.size();
+            if let Some(max_size) = self.options.max_file_size {
+                if size > max_size {
+                    log_skip(
+                        Path::new(&display_name),
+                        &SkipReason::TooLarge { size, max_size },
+                    );
+                    continue;
+                }
+            }
+            if !self.reserve(size, &display_name) {
+                continue;
+            }
+
+            // Each entry gets its own directory so its real file name survives extraction
+            let target_dir = self.extraction.path().join(self.matched.len().to_string());
+            fs::create_dir_all(&target_dir)?;
+            let file_name = inner_path.file_name().unwrap_or(inner_path.as_os_str());
+            let target = target_dir.join(file_name);
+            // `take` bounds the copy even if the entry lies about its size
+            io::copy(&mut entry.by_ref().take(size), &mut File::create(&target)?)?;
+            self.matched.push(MatchedFile {
+                path: target,
+                display_path: PathBuf::from(display_name),
+                size,
+                language: None,
+                extraction: Some(Arc::clone(&self.extraction)),
+                commit: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn in_ignored_dir(&self, parent: &Path) -> bool {
+        parent.components().any(|component| {
+            self.options
+                .ignored_dirs
+                .iter()
+                .any(|dir| component.as_os_str() == dir.as_str())
+        })
+    }
+
+    /// Counts `size` bytes against [`MAX_ARCHIVE_BYTES`], refusing once the budget is spent.
+    fn reserve(&mut self, size: u64, display_name: &str) -> bool {
+        if self.extracted_bytes + size > MAX_ARCHIVE_BYTES {
+            warn!(
+                event = "skipped", path = display_name, reason = "archive_limit";
+                "Skipping {}: archive expands beyond {} bytes", display_name, MAX_ARCHIVE_BYTES
+            );
+            return false;
+        }
+        self.extracted_bytes += size;
+        true
+    }
+}
@@ -0,0 +1,409 @@
+/// How comments and strings are written in one family of languages. Strings are tracked only
+/// so that comment markers inside them are left alone.
+struct Syntax {
+    /// Starts a comment that runs to the end of the line
+    line_comment: Option<&'static str>,
+    /// `#` comments, as in Python and shell
+    hash_comments: bool,
+    /// In shell-like languages `#` only starts a comment at the beginning of a word, so
+    /// `$#` and `${#list}` survive
+    hash_needs_boundary: bool,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Rust, Swift, Kotlin, and Scala allow block comments inside block comments
+    nested_blocks: bool,
+    /// Quote characters that open a string closed by the same character
+    quotes: &'static [char],
+    /// `"""` and `'''` strings (and Python docstrings)
+    triple_quotes: bool,
+    /// Backtick strings: JS template literals and Go raw strings
+    backticks: bool,
+    /// Whether `\` escapes inside backtick strings; Go raw strings take it literally
+    backtick_escapes: bool,
+    /// `r#"..."#` raw strings, and `'` doubling as a lifetime sigil
+    rust: bool,
+    python: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comment: Some("//"),
+    hash_comments: false,
+    hash_needs_boundary: false,
+    block_comment: Some(("/*", "*/")),
+    nested_blocks: false,
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    backticks: false,
+    backtick_escapes: false,
+    rust: false,
+    python: false,
+};
+
+const HASH: Syntax = Syntax {
+    line_comment: None,
+    hash_comments: true,
+    hash_needs_boundary: true,
+    block_comment: None,
+    nested_blocks: false,
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    backticks: false,
+    backtick_escapes: false,
+    rust: false,
+    python: false,
+};
+
+impl Syntax {
+    /// Takes the language names produced by [`detect_language`](crate::detect_language).
+    fn for_language(language: &str) -> Option<Syntax> {
+        let syntax = match language {
+            "rust" => Syntax {
+                nested_blocks: true,
+                quotes: &['"'],
+                rust: true,
+                ..C_LIKE
+            },
+            "javascript" | "jsx" | "typescript" | "tsx" => Syntax {
+                backticks: true,
+                backtick_escapes: true,
+                ..C_LIKE
+            },
+            "go" => Syntax {
+                backticks: true,
+                ..C_LIKE
+            },
+            "swift" | "kotlin" | "scala" => Syntax {
+                nested_blocks: true,
+                triple_quotes: true,
+                ..C_LIKE
+            },
+            "java" | "csharp" => Syntax {
+                triple_quotes: true,
+                ..C_LIKE
+            },
+            "c" | "cpp" | "dart" | "groovy" => C_LIKE,
+            // A `//` in CSS is usually part of an unquoted URL, not a comment
+            "css" => Syntax {
+                line_comment: None,
+                ..C_LIKE
+            },
+            "php" => Syntax {
+                hash_comments: true,
+                hash_needs_boundary: true,
+                ..C_LIKE
+            },
+            "python" => Syntax {
+                hash_needs_boundary: false,
+                triple_quotes: true,
+                python: true,
+                ..HASH
+            },
+            "toml" => Syntax {
+                triple_quotes: true,
+                ..HASH
+            },
+            "bash" | "zsh" | "ruby" | "perl" | "r" | "makefile" | "dockerfile" => HASH,
+            _ => return None,
+        };
+        Some(syntax)
+    }
+}
+
+/// Removes comments (and Python docstrings) from `contents`, written in `language` as named
+/// by [`detect_language`](crate::detect_language), e.g. `rust` or `python`.
+///
+/// Comment markers inside string literals are kept, lines left empty by the removal are
+/// dropped, and a leading `#!` line survives. Languages without known comment syntax are
+/// returned unchanged.
+pub fn strip_comments(contents: &str, language: &str) -> String {
+    let Some(syntax) = Syntax::for_language(language) else {
+        return contents.to_string();
+    };
+    Stripper::new(contents, &syntax).run()
+}
+
+struct Stripper<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    syntax: &'a Syntax,
+    output: String,
+    line: String,
+    /// Whether a comment was removed from the current line
+    stripped: bool,
+    /// Last non-whitespace character kept outside comments, to recognise docstrings
+    last_code: Option<char>,
+}
+
+impl<'a> Stripper<'a> {
+    fn new(contents: &str, syntax: &'a Syntax) -> Self {
+        Stripper {
+            chars: contents.chars().collect(),
+            pos: 0,
+            syntax,
+            output: String::with_capacity(contents.len()),
+            line: String::new(),
+            stripped: false,
+            last_code: None,
+        }
+    }
+
+    fn run(mut self) -> String {
+        if self.syntax.hash_comments && self.starts_with("#!") {
+            while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+                self.keep(self.chars[self.pos]);
+                self.pos += 1;
+            }
+            // A module docstring may follow the `#!` line
+            self.last_code = None;
+        }
+        while self.pos < self.chars.len() {
+            let c = self.chars[self.pos];
+            if c == '\n' {
+                self.end_line();
+                self.pos += 1;
+            } else if let Some(marker) = self.syntax.line_comment.filter(|m| self.starts_with(m)) {
+                self.pos += marker.len();
+                self.skip_line();
+            } else if c == '#' && self.syntax.hash_comments && self.at_word_start() {
+                self.skip_line();
+            } else if let Some((open, close)) = self
+                .syntax
+                .block_comment
+                .filter(|(open, _)| self.starts_with(open))
+            {
+                self.pos += open.len();
+                self.skip_block(open, close);
+            } else if self.syntax.triple_quotes
+                && (self.starts_with("\"\"\"") || self.starts_with("'''"))
+            {
+                self.triple_quoted(c);
+            } else if self.syntax.rust && self.raw_string_start().is_some() {
+                self.raw_string();
+            } else if self.syntax.rust && c == '\'' {
+                self.rust_quote();
+            } else if self.syntax.quotes.contains(&c) {
+                self.string(c, true);
+            } else if c == '`' && self.syntax.backticks {
+                self.string(c, self.syntax.backtick_escapes);
+            } else {
+                self.keep(c);
+                self.pos += 1;
+            }
+        }
+        if !self.line.is_empty() || self.stripped {
+            self.flush_line(false);
+        }
+        self.output
+    }
+
+    fn starts_with(&self, marker: &str) -> bool {
+        let mut chars = marker.chars();
+        let len = marker.chars().count();
+        self.pos + len <= self.chars.len()
+            && self.chars[self.pos..self.pos + len]
+                .iter()
+                .all(|&c| Some(c) == chars.next())
+    }
+
+    fn at_word_start(&self) -> bool {
+        !self.syntax.hash_needs_boundary
+            || self.pos == 0
+            || matches!(self.chars[self.pos - 1], ' ' | '\t' | '\n' | ';')
+    }
+
+    fn keep(&mut self, c: char) {
+        self.line.push(c);
+        if !c.is_whitespace() {
+            self.last_code = Some(c);
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.flush_line(true);
+    }
+
+    /// Emits the current line, unless removing a comment left nothing on it.
+    fn flush_line(&mut self, newline: bool) {
+        if self.stripped {
+            let trimmed = self.line.trim_end();
+            if !trimmed.trim_start().is_empty() {
+                self.output.push_str(trimmed);
+                if newline {
+                    self.output.push('\n');
+                }
+            }
+        } else {
+            self.output.push_str(&self.line);
+            if newline {
+                self.output.push('\n');
+            }
+        }
+        self.line.clear();
+        self.stripped = false;
+    }
+
+    /// Skips to the end of the line, leaving the newline for the main loop.
+    fn skip_line(&mut self) {
+        self.stripped = true;
+        while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_block(&mut self, open: &str, close: &str) {
+        self.stripped = true;
+        let mut depth = 1;
+        while self.pos < self.chars.len() {
+            if self.starts_with(close) {
+                self.pos += close.len();
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            } else if self.syntax.nested_blocks && self.starts_with(open) {
+                self.pos += open.len();
+                depth += 1;
+            } else {
+                if self.chars[self.pos] == '\n' {
+                    self.end_line();
+                    self.stripped = true;
+                }
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Copies a string literal opened by `quote` through its closing quote.
+    fn string(&mut self, quote: char, escapes: bool) {
+        self.keep(quote);
+        self.pos += 1;
+        while self.pos < self.chars.len() {
+            let c = self.chars[self.pos];
+            self.pos += 1;
+            if c == '\n' {
+                self.end_line();
+                continue;
+            }
+            self.keep(c);
+            if escapes && c == '\\' {
+                if let Some(&next) = self.chars.get(self.pos) {
+                    if next == '\n' {
+                        self.end_line();
+                    } else {
+                        self.keep(next);
+                    }
+                    self.pos += 1;
+                }
+            } else if c == quote {
+                return;
+            }
+        }
+    }
+
+    /// A `"""` or `'''` string. In Python one standing alone as a statement after a `:` or at
+    /// the top of the file is a docstring and is removed.
+    fn triple_quoted(&mut self, quote: char) {
+        let delimiter: String = std::iter::repeat_n(quote, 3).collect();
+        let is_docstring = self.syntax.python
+            && self.line.trim().is_empty()
+            && self.last_code.is_none_or(|c| c == ':');
+        if is_docstring {
+            self.pos += 3;
+            self.stripped = true;
+            while self.pos < self.chars.len() && !self.starts_with(&delimiter) {
+                if self.chars[self.pos] == '\\' {
+                    self.pos += 1;
+                } else if self.chars[self.pos] == '\n' {
+                    self.end_line();
+                    self.stripped = true;
+                }
+                self.pos += 1;
+            }
+            self.pos = (self.pos + 3).min(self.chars.len());
+            return;
+        }
+
+        for c in delimiter.chars() {
+            self.keep(c);
+        }
+        self.pos += 3;
+        while self.pos < self.chars.len() {
+            if self.starts_with(&delimiter) {
+                for c in delimiter.chars() {
+                    self.keep(c);
+                }
+                self.pos += 3;
+                return;
+            }
+            let c = self.chars[self.pos];
+            self.pos += 1;
+            if c == '\n' {
+                self.end_line();
+            } else {
+                self.keep(c);
+                if c == '\\' && self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+                    self.keep(self.chars[self.pos]);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Number of `#`s in a raw string starting here (`r"`, `r#"`, `br##"`, ...).
+    fn raw_string_start(&self) -> Option<usize> {
+        let previous = self.pos.checked_sub(1).map(|i| self.chars[i]);
+        if previous.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        let mut i = self.pos;
+        if self.chars.get(i) == Some(&'b') {
+            i += 1;
+        }
+        if self.chars.get(i) != Some(&'r') {
+            return None;
+        }
+        i += 1;
+        let hashes = self.chars[i..].iter().take_while(|&&c| c == '#').count();
+        (self.chars.get(i + hashes) == Some(&'"')).then_some(hashes)
+    }
+
+    fn raw_string(&mut self) {
+        let hashes = self.raw_string_start().unwrap_or(0);
+        let closing: String = std::iter::once('"')
+            .chain(std::iter::repeat_n('#', hashes))
+            .collect();
+        while self.chars[self.pos] != '"' {
+            self.keep(self.chars[self.pos]);
+            self.pos += 1;
+        }
+        self.keep('"');
+        self.pos += 1;
+        while self.pos < self.chars.len() {
+            if self.starts_with(&closing) {
+                for c in closing.chars() {
+                    self.keep(c);
+                }
+                self.pos += closing.len();
+                return;
+            }
+            let c = self.chars[self.pos];
+            self.pos += 1;
+            if c == '\n' {
+                self.end_line();
+            } else {
+                self.keep(c);
+            }
+        }
+    }
+
+    /// A Rust `'` is a char literal (`'a'`, `'\n'`) or a lifetime (`'a`), which has no end.
+    fn rust_quote(&mut self) {
+        let next = self.chars.get(self.pos + 1).copied();
+        let is_char = next == Some('\\') || self.chars.get(self.pos + 2) == Some(&'\'');
+        if is_char {
+            self.string('\'', true);
+        } else {
+            self.keep('\'');
+            self.pos += 1;
+        }
+    }
+}
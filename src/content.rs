@@ -0,0 +1,349 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use log::{debug, error, info};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tempfile::NamedTempFile;
+use tiktoken_rs::cl100k_base_singleton;
+
+use crate::search::{log_skip, MatchedFile, SearchOptions, SkipReason};
+
+pub fn count_tokens(text: &str) -> usize {
+    cl100k_base_singleton()
+        .encode_with_special_tokens(text)
+        .len()
+}
+
+/// Returns the token count cache directory, e.g. `~/.cache/llm-context-builder/tokens`.
+pub fn token_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("tokens"))
+}
+
+/// Counts tokens like [`count_tokens`], remembering the result under `cache_dir` keyed by a
+/// hash of `text`, so unchanged files aren't tokenized again on the next run.
+///
+/// Each count is its own file, written to a temporary name and renamed into place, so
+/// concurrent threads and processes can share the cache. A cache that can't be read or
+/// written falls back to counting.
+pub fn cached_token_count(cache_dir: &Path, text: &str) -> usize {
+    let mut hasher = blake3::Hasher::new();
+    // The key also names the tokenizer, so switching encodings doesn't reuse stale counts
+    hasher.update(b"cl100k_base\0");
+    hasher.update(text.as_bytes());
+    let key = hasher.finalize().to_hex();
+    let entry = cache_dir.join(&key[..2]).join(key.as_str());
+    if let Some(tokens) = fs::read_to_string(&entry)
+        .ok()
+        .and_then(|cached| cached.trim().parse().ok())
+    {
+        return tokens;
+    }
+
+    let tokens = count_tokens(text);
+    if let Err(e) = write_cache_entry(&entry, tokens) {
+        debug!("Could not cache token count in {}: {}", entry.display(), e);
+    }
+    tokens
+}
+
+fn write_cache_entry(entry: &Path, tokens: usize) -> io::Result<()> {
+    let dir = entry.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let mut file = NamedTempFile::new_in(dir)?;
+    write!(file, "{}", tokens)?;
+    file.persist(entry).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Sniffs the first 8KB of a file: a NUL byte or invalid UTF-8 means it is not text.
+///
+/// Printing only skips invalid UTF-8 as binary with `--strict-utf8`; by default such files
+/// are treated as text in a legacy encoding and decoded lossily.
+pub fn is_probably_binary(path: &Path) -> io::Result<bool> {
+    sniff_binary(path, true)
+}
+
+/// [`is_probably_binary`], except that invalid UTF-8 only counts as binary with `strict_utf8`.
+pub(crate) fn sniff_binary(path: &Path, strict_utf8: bool) -> io::Result<bool> {
+    let mut buffer = Vec::with_capacity(8192);
+    File::open(path)?.take(8192).read_to_end(&mut buffer)?;
+    if buffer.contains(&0) {
+        return Ok(true);
+    }
+    if !strict_utf8 {
+        return Ok(false);
+    }
+    match std::str::from_utf8(&buffer) {
+        Ok(_) => Ok(false),
+        // A multi-byte character cut off at the 8KB boundary is still text
+        Err(e) => Ok(e.error_len().is_some()),
+    }
+}
+
+pub(crate) const UTF8_BOM: &str = "\u{feff}";
+
+pub(crate) fn strip_bom(text: &str) -> &str {
+    text.strip_prefix(UTF8_BOM).unwrap_or(text)
+}
+
+/// Decodes bytes as UTF-8, replacing invalid sequences (e.g. Latin-1 text) with U+FFFD.
+pub(crate) fn decode_lossy(bytes: &[u8], display_path: &Path) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    if let Cow::Owned(_) = text {
+        info!(
+            "{} is not valid UTF-8; invalid bytes were replaced",
+            display_path.display()
+        );
+    }
+    strip_bom(&text).to_string()
+}
+
+/// Reads a matched file for printing. Skipped binary files give `Ok(None)`; read errors are
+/// logged before they are returned.
+pub(crate) fn read_contents(
+    file: &MatchedFile,
+    options: &SearchOptions,
+) -> io::Result<Option<String>> {
+    let is_binary = match sniff_binary(&file.path, options.strict_utf8) {
+        Ok(is_binary) => is_binary,
+        Err(e) => {
+            error!(
+                event = "skipped", path:% = file.display_path.display(), reason = "read_error";
+                "Error reading file {}: {}", file.path.display(), e
+            );
+            return Err(e);
+        }
+    };
+    if is_binary && !options.include_binary {
+        log_skip(&file.display_path, &SkipReason::Binary);
+        return Ok(None);
+    }
+    let mapped = match options.mmap_threshold {
+        Some(threshold) if file.size >= threshold && options.head.is_none() => {
+            // SAFETY: the map is only read while the file is decoded. Another process
+            // truncating the file meanwhile can still fault the read, which `--mmap` documents.
+            match File::open(&file.path).and_then(|file| unsafe { Mmap::map(&file) }) {
+                Ok(map) => Some(map),
+                Err(e) => {
+                    debug!(
+                        "Reading {} without a memory map: {}",
+                        file.path.display(),
+                        e
+                    );
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let contents = if let Some(limit) = options.head {
+        read_head(file, limit, is_binary, options.strict_utf8)
+    } else if let Some(map) = mapped {
+        decode_mapped(&map, file, is_binary, options.strict_utf8)
+    } else if is_binary {
+        fs::read(&file.path).map(|bytes| BASE64_STANDARD.encode(bytes))
+    } else if options.strict_utf8 {
+        fs::read_to_string(&file.path).map(|text| strip_bom(&text).to_string())
+    } else {
+        fs::read(&file.path).map(|bytes| decode_lossy(&bytes, &file.display_path))
+    };
+    match contents {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) => {
+            error!(
+                event = "skipped", path:% = file.display_path.display(), reason = "read_error";
+                "Error reading file {}: {}", file.path.display(), e
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Decodes a memory-mapped file like the buffered reads in [`read_contents`]. The decoded
+/// text is an owned copy, as for any other file.
+fn decode_mapped(
+    bytes: &[u8],
+    file: &MatchedFile,
+    is_binary: bool,
+    strict_utf8: bool,
+) -> io::Result<String> {
+    if is_binary {
+        Ok(BASE64_STANDARD.encode(bytes))
+    } else if strict_utf8 {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(strip_bom(text).to_string())
+    } else {
+        Ok(decode_lossy(bytes, &file.display_path))
+    }
+}
+
+const HEAD_MARKER: &str = "... (truncated)";
+
+/// Reads at most `limit` bytes for `--head`, backing off to the last whole UTF-8 character
+/// and ending with [`HEAD_MARKER`] when the file is longer. Binary files are cut at the byte.
+fn read_head(
+    file: &MatchedFile,
+    limit: u64,
+    is_binary: bool,
+    strict_utf8: bool,
+) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    File::open(&file.path)?
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut bytes)?;
+    let truncated = bytes.len() as u64 > limit;
+    bytes.truncate(limit as usize);
+    if truncated && !is_binary {
+        if let Err(e) = std::str::from_utf8(&bytes) {
+            // Only a character split by the cut is dropped; other invalid bytes are kept
+            if e.error_len().is_none() {
+                bytes.truncate(e.valid_up_to());
+            }
+        }
+    }
+    let mut contents = if is_binary {
+        BASE64_STANDARD.encode(&bytes)
+    } else if strict_utf8 {
+        let text =
+            String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        strip_bom(&text).to_string()
+    } else {
+        decode_lossy(&bytes, &file.display_path)
+    };
+    if truncated {
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(HEAD_MARKER);
+        contents.push('\n');
+    }
+    Ok(contents)
+}
+
+pub(crate) const REDACTED: &str = "***REDACTED***";
+
+/// Combines the names and raw contents of `files` (`(name, source)` pairs) into one hash that
+/// only changes when the set of files or any of their contents does. Names are sorted first,
+/// so discovery order doesn't matter; unreadable files contribute their name alone.
+pub fn manifest_hash(files: &[(PathBuf, PathBuf)]) -> blake3::Hash {
+    let mut entries: Vec<(String, Option<[u8; 32]>)> = files
+        .par_iter()
+        .map(|(name, source)| {
+            let hash = match fs::read(source) {
+                Ok(contents) => Some(*blake3::hash(&contents).as_bytes()),
+                Err(e) => {
+                    error!("Error reading file {}: {}", source.display(), e);
+                    None
+                }
+            };
+            // Forward slashes keep the hash identical across platforms
+            (name.to_string_lossy().replace('\\', "/"), hash)
+        })
+        .collect();
+    entries.sort();
+
+    let mut hasher = blake3::Hasher::new();
+    for (name, hash) in &entries {
+        hasher.update(name.as_bytes());
+        hasher.update(&[0]);
+        if let Some(hash) = hash {
+            hasher.update(hash);
+        }
+        hasher.update(b"\n");
+    }
+    hasher.finalize()
+}
+
+/// Patterns for common credentials. Group 1 is kept, e.g. the `API_KEY=` part of an
+/// assignment, and the rest of the match is replaced.
+pub(crate) fn secret_patterns() -> &'static [regex::Regex] {
+    static PATTERNS: OnceLock<Vec<regex::Regex>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            // Private key blocks (PEM), header through footer
+            r"()-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+            // AWS access key IDs
+            r"()\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+            // GitHub personal access, OAuth, app, and refresh tokens
+            r"()\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+            r"()\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+            // Generic `API_KEY = "..."` style assignments
+            r#"(?i)(\b[a-z0-9_.-]*(?:api[_-]?key|secret|token|passw(?:or)?d)["']?\s*[:=]\s*["']?)[A-Za-z0-9_\-+/=.~]{8,}"#,
+        ]
+        .iter()
+        .map(|pattern| regex::Regex::new(pattern).expect("secret pattern is valid"))
+        .collect()
+    })
+}
+
+/// Replaces anything that looks like a secret with `***REDACTED***`, returning the
+/// scrubbed text and how many replacements were made.
+pub fn redact(contents: &str) -> (String, usize) {
+    let mut redacted = contents.to_string();
+    let mut count = 0;
+    for pattern in secret_patterns() {
+        redacted = pattern
+            .replace_all(&redacted, |caps: &regex::Captures| {
+                count += 1;
+                format!("{}{}", &caps[1], REDACTED)
+            })
+            .into_owned();
+    }
+    (redacted, count)
+}
+
+/// Line ending that printed contents are rewritten to with `--normalize-eol`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// Rewrites CRLF and lone CR line endings to LF. Borrows when there is nothing to change.
+pub(crate) fn to_lf(contents: &str) -> Cow<'_, str> {
+    if !contents.contains('\r') {
+        return Cow::Borrowed(contents);
+    }
+    Cow::Owned(contents.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// Prefixes every line with a right-aligned number, e.g. `  12 | let x = 1;`.
+pub(crate) fn add_line_numbers(contents: &str) -> String {
+    let width = contents.lines().count().max(1).to_string().len();
+    let mut numbered = String::with_capacity(contents.len() + contents.len() / 8);
+    for (i, line) in contents.lines().enumerate() {
+        numbered.push_str(&format!("{:>width$} | {}\n", i + 1, line, width = width));
+    }
+    numbered
+}
+
+/// Keeps the first and last `max_lines / 2` lines of a long file, replacing the middle with
+/// a `... (truncated N lines) ...` marker so imports and trailing exports survive.
+pub(crate) fn truncate_lines(contents: &str, max_lines: usize) -> Option<String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() <= max_lines {
+        return None;
+    }
+    let head = max_lines / 2;
+    let tail = max_lines - head;
+    let mut truncated = String::new();
+    for line in &lines[..head] {
+        truncated.push_str(line);
+        truncated.push('\n');
+    }
+    truncated.push_str(&format!(
+        "... (truncated {} lines) ...\n",
+        lines.len() - head - tail
+    ));
+    for line in &lines[lines.len() - tail..] {
+        truncated.push_str(line);
+        truncated.push('\n');
+    }
+    Some(truncated)
+}
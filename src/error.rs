@@ -0,0 +1,92 @@
+use std::error;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use thiserror::Error;
+
+/// Why parsing a repository URL, fetching it, or searching files failed.
+///
+/// Callers can tell a bad URL from a failed download, a bad filter, or a local I/O problem
+/// without inspecting the message, e.g. to pick an exit code.
+#[derive(Debug, Error)]
+pub enum ContextError {
+    /// A repository URL or `owner/repo` shorthand that can't be parsed
+    #[error("{0}")]
+    InvalidGithubUrl(String),
+    /// A URL on a host other than GitHub, GitLab, Bitbucket, or the `--github-host`
+    #[error(
+        "Not a valid GitHub, GitLab, or Bitbucket URL (pass --github-host {0} for GitHub Enterprise)"
+    )]
+    NotAGithubUrl(String),
+    /// A `--github-host` that isn't a bare hostname
+    #[error("Invalid GitHub host '{0}': expected a hostname like github.mycorp.com")]
+    InvalidGithubHost(String),
+    /// The code host refused or failed the download, or the connection broke
+    #[error(transparent)]
+    Download(Box<dyn error::Error + Send + Sync>),
+    /// The archive is corrupt or would write outside the target directory
+    #[error(transparent)]
+    Extraction(Box<dyn error::Error + Send + Sync>),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A pattern in `--include`, `--exclude`, or `--ignored-files` that isn't a valid glob
+    #[error("Invalid glob '{pattern}': {source}")]
+    InvalidGlob {
+        pattern: String,
+        #[source]
+        source: globset::Error,
+    },
+    /// A `.llmignore` file with a line that isn't a valid gitignore pattern
+    #[error("Invalid {}: {source}", path.display())]
+    InvalidIgnoreFile {
+        path: PathBuf,
+        #[source]
+        source: ignore::Error,
+    },
+    /// Walking a search directory failed, e.g. on an unreadable directory
+    #[error(transparent)]
+    Walk(#[from] ignore::Error),
+    /// A `--filter-command` or `transforms` command that could not be started
+    #[error("Could not run '{command}': {source}")]
+    CommandNotRun {
+        command: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A `--filter-command` or `transforms` command that exited unsuccessfully, with what it
+    /// printed on stderr
+    #[error("'{command}' failed ({status}){}", stderr_suffix(.stderr))]
+    CommandFailed {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// Contents given to the `pretty-json` transform that aren't JSON
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    /// `--interactive` without a terminal to show the checklist on
+    #[error("--interactive needs a terminal to show the file checklist")]
+    NoTerminal,
+    /// An `--append-file` that is a directory or another kind of non-file
+    #[error("Cannot append {}: not a file", .0.display())]
+    NotAFile(PathBuf),
+    /// Writing the `--bundle` zip archive failed
+    #[error("Failed to write bundle: {0}")]
+    Bundle(#[from] zip::result::ZipError),
+    /// `--only-changed` on a directory outside any git working tree, or in a bare repository
+    #[error(
+        "--only-changed needs a git working tree, but {} is not inside one",
+        .0.display()
+    )]
+    NotAWorkTree(PathBuf),
+    /// Reading the status of a git repository failed
+    #[error("git: {0}")]
+    Git(#[from] git2::Error),
+}
+
+fn stderr_suffix(stderr: &str) -> String {
+    match stderr.trim() {
+        "" => String::new(),
+        stderr => format!(": {}", stderr),
+    }
+}
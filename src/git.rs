@@ -0,0 +1,145 @@
+use git2::{ErrorCode, Repository, StatusOptions};
+use log::debug;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::error::ContextError;
+use crate::search::MatchedFile;
+
+/// The last commit that touched a file, shown in its header with `--with-git-info`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitInfo {
+    /// Abbreviated commit hash
+    pub hash: String,
+    pub author: String,
+    /// Author date as `YYYY-MM-DD`
+    pub date: String,
+}
+
+/// Returns the canonical paths of the files under `directory` with uncommitted changes, for
+/// `--only-changed`: modified or added in the working tree or the index, or untracked (but not
+/// ignored), as reported by libgit2. Deleted files are left out since there is nothing to
+/// print.
+///
+/// It is an error when `directory` isn't inside a git working tree.
+pub(crate) fn changed_files(directory: &Path) -> Result<HashSet<PathBuf>, ContextError> {
+    let not_a_work_tree = || ContextError::NotAWorkTree(directory.to_path_buf());
+    let repo = match Repository::discover(directory) {
+        Ok(repo) => repo,
+        Err(e) if e.code() == ErrorCode::NotFound => return Err(not_a_work_tree()),
+        Err(e) => return Err(e.into()),
+    };
+    let workdir = repo.workdir().ok_or_else(not_a_work_tree)?;
+    let statuses = repo.statuses(Some(
+        StatusOptions::new()
+            .include_untracked(true)
+            .recurse_untracked_dirs(true),
+    ))?;
+
+    let directory = fs::canonicalize(directory)?;
+    let mut changed = HashSet::new();
+    for entry in statuses.iter() {
+        if entry.status().is_ignored() {
+            continue;
+        }
+        // Status paths are relative to the top of the working tree and use forward slashes
+        let path = workdir.join(String::from_utf8_lossy(entry.path_bytes()).as_ref());
+        if let Ok(canonical) = fs::canonicalize(&path) {
+            if canonical.starts_with(&directory) {
+                changed.insert(canonical);
+            }
+        }
+    }
+    debug!(
+        "{} files have uncommitted changes under {}",
+        changed.len(),
+        directory.display()
+    );
+    Ok(changed)
+}
+
+/// Fills in [`MatchedFile::commit`] from the history of the git repository containing
+/// `directory`.
+///
+/// History is read newest first in a single `git log` and abandoned as soon as every file has
+/// been seen. Untracked files keep no commit, and nothing happens when git is missing or the
+/// directory isn't part of a repository.
+pub(crate) fn annotate_commits(directory: &Path, files: &mut [MatchedFile]) {
+    let mut pending: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        if let Ok(relative) = file.path.strip_prefix(directory) {
+            pending.entry(relative.to_path_buf()).or_default().push(i);
+        }
+    }
+    if pending.is_empty() {
+        return;
+    }
+
+    let child = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args([
+            "-c",
+            "core.quotePath=off",
+            "log",
+            "--relative",
+            "--no-renames",
+            "--name-only",
+            "--format=%x00%h%x09%an%x09%as",
+            "--",
+            ".",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            debug!("Not adding git info: could not run git: {}", e);
+            return;
+        }
+    };
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut commit: Option<CommitInfo> = None;
+    for line in BufReader::new(stdout).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if let Some(header) = line.strip_prefix('\0') {
+            let mut fields = header.splitn(3, '\t');
+            commit = Some(CommitInfo {
+                hash: fields.next().unwrap_or_default().to_string(),
+                author: fields.next().unwrap_or_default().to_string(),
+                date: fields.next().unwrap_or_default().to_string(),
+            });
+            continue;
+        }
+        let (Some(commit), false) = (&commit, line.is_empty()) else {
+            continue;
+        };
+        if let Some(indices) = pending.remove(Path::new(&line)) {
+            for i in indices {
+                files[i].commit = Some(commit.clone());
+            }
+            if pending.is_empty() {
+                break;
+            }
+        }
+    }
+    // Stopping early leaves the rest of the history unread
+    let _ = child.kill();
+    match child.wait() {
+        Ok(status) if !status.success() && commit.is_none() => {
+            debug!(
+                "Not adding git info: {} is not in a git repository",
+                directory.display()
+            );
+        }
+        _ => {}
+    }
+}
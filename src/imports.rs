@@ -0,0 +1,314 @@
+use regex::Regex;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::comments::strip_comments;
+
+/// Returns the local files that `entries` reference through Rust `mod`/`use` declarations and
+/// Python imports, transitively, for `--follow-imports`.
+///
+/// Only existing files under `directory` are returned, excluding the entries themselves.
+/// `max_depth` bounds the number of import hops from an entry (`Some(1)` adds only direct
+/// imports). References to other crates, packages, or the standard library are ignored.
+pub(crate) fn follow_imports(
+    entries: &[PathBuf],
+    directory: &Path,
+    max_depth: Option<usize>,
+) -> Vec<PathBuf> {
+    let mut seen: HashSet<PathBuf> = entries.iter().cloned().collect();
+    let mut queue: VecDeque<(PathBuf, usize)> =
+        entries.iter().map(|entry| (entry.clone(), 0)).collect();
+    let mut found = Vec::new();
+
+    while let Some((file, depth)) = queue.pop_front() {
+        if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            continue;
+        }
+        let Ok(contents) = fs::read_to_string(&file) else {
+            continue;
+        };
+        let imports = match file.extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => rust_imports(&file, &contents, directory),
+            Some("py") => python_imports(&file, &contents, directory),
+            _ => continue,
+        };
+        for import in imports {
+            if import.starts_with(directory) && seen.insert(import.clone()) {
+                found.push(import.clone());
+                queue.push_back((import, depth + 1));
+            }
+        }
+    }
+    found
+}
+
+fn rust_mod_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+([A-Za-z_][A-Za-z0-9_]*)\s*;")
+            .expect("mod pattern is valid")
+    })
+}
+
+fn rust_use_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+([^;]+);")
+            .expect("use pattern is valid")
+    })
+}
+
+/// Directory holding the submodules of the Rust file `file`: its own directory for crate
+/// roots and `mod.rs`, otherwise a directory named after it (`a/b.rs` → `a/b/`).
+fn rust_module_dir(file: &Path) -> PathBuf {
+    let dir = file.parent().unwrap_or(Path::new(""));
+    match file.file_name().and_then(|name| name.to_str()) {
+        Some("main.rs" | "lib.rs" | "mod.rs") => dir.to_path_buf(),
+        _ => dir.join(file.file_stem().unwrap_or_default()),
+    }
+}
+
+/// Nearest directory at or above `file` holding a `main.rs` or `lib.rs`, where `crate::`
+/// paths start.
+fn rust_crate_root(file: &Path, directory: &Path) -> Option<PathBuf> {
+    file.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(directory))
+        .find(|dir| rust_root_file(dir).is_some())
+        .map(Path::to_path_buf)
+}
+
+fn rust_root_file(crate_root: &Path) -> Option<PathBuf> {
+    ["lib.rs", "main.rs"]
+        .into_iter()
+        .map(|name| crate_root.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The library name (as written in paths) and source directory of the package whose crate
+/// root is in `crate_root`, when that package has a `lib.rs`.
+fn rust_library(crate_root: &Path) -> Option<(String, PathBuf)> {
+    static NAME: OnceLock<Regex> = OnceLock::new();
+    let name = NAME.get_or_init(|| {
+        Regex::new(r#"(?m)^\s*name\s*=\s*"([^"]+)""#).expect("name pattern is valid")
+    });
+    if !crate_root.join("lib.rs").is_file() {
+        return None;
+    }
+    let manifest = fs::read_to_string(crate_root.parent()?.join("Cargo.toml")).ok()?;
+    // The first `name` belongs to `[package]`, which comes before any `[lib]` or `[[bin]]`
+    let package = name.captures(&manifest)?[1].replace('-', "_");
+    Some((package, crate_root.to_path_buf()))
+}
+
+/// Follows module names down from `base` as far as files exist (`a/b.rs` or `a/b/mod.rs`),
+/// returning the deepest one. Item names such as a trailing `Type` simply end the walk.
+fn resolve_rust_path(base: &Path, segments: &[&str]) -> Option<PathBuf> {
+    let mut dir = base.to_path_buf();
+    let mut found = None;
+    for segment in segments {
+        let file = dir.join(format!("{}.rs", segment));
+        let mod_file = dir.join(segment).join("mod.rs");
+        if file.is_file() {
+            found = Some(file);
+        } else if mod_file.is_file() {
+            found = Some(mod_file);
+        } else {
+            break;
+        }
+        dir = dir.join(segment);
+    }
+    found
+}
+
+/// Expands a `use` tree such as `crate::a::{b, c::{d, e}}` into its individual paths.
+fn expand_use_tree(tree: &str) -> Vec<Vec<String>> {
+    static ALIAS: OnceLock<Regex> = OnceLock::new();
+    let alias = ALIAS.get_or_init(|| Regex::new(r"\s+as\s+\w+").expect("alias pattern is valid"));
+    let tree: String = alias
+        .replace_all(tree, "")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let mut paths = Vec::new();
+    expand_use_tree_into(&tree, &mut Vec::new(), &mut paths);
+    paths
+}
+
+fn expand_use_tree_into(tree: &str, prefix: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+    let Some(open) = tree.find('{') else {
+        let mut full = prefix.clone();
+        full.extend(
+            tree.split("::")
+                .filter(|segment| !segment.is_empty())
+                .map(str::to_string),
+        );
+        paths.push(full);
+        return;
+    };
+    let Some(close) = tree.rfind('}') else {
+        return;
+    };
+    let head: Vec<String> = tree[..open]
+        .split("::")
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect();
+    let depth = prefix.len();
+    prefix.extend(head);
+    for item in split_top_level(&tree[open + 1..close]) {
+        expand_use_tree_into(item, prefix, paths);
+    }
+    prefix.truncate(depth);
+}
+
+/// Splits on commas that aren't inside nested braces.
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&list[start..]);
+    items.into_iter().filter(|item| !item.is_empty()).collect()
+}
+
+fn rust_imports(file: &Path, contents: &str, directory: &Path) -> Vec<PathBuf> {
+    // Comments could otherwise mention `mod` or `use` lines that aren't there
+    let contents = strip_comments(contents, "rust");
+    let module_dir = rust_module_dir(file);
+    let mut imports = Vec::new();
+
+    for captures in rust_mod_pattern().captures_iter(&contents) {
+        imports.extend(resolve_rust_path(&module_dir, &[&captures[1]]));
+    }
+
+    let crate_root = rust_crate_root(file, directory);
+    let library = crate_root.as_deref().and_then(rust_library);
+    for captures in rust_use_pattern().captures_iter(&contents) {
+        for path in expand_use_tree(&captures[1]) {
+            let segments: Vec<&str> = path.iter().map(String::as_str).collect();
+            // Items used straight from a crate root, often re-exports, point at its root file
+            let (base, rest, root_file) = match segments.split_first() {
+                Some((&"crate", rest)) => match &crate_root {
+                    Some(root) => (root.clone(), rest, rust_root_file(root)),
+                    None => continue,
+                },
+                Some((&"self", rest)) => (module_dir.clone(), rest, None),
+                Some((&"super", _)) => {
+                    let supers = segments.iter().take_while(|&&s| s == "super").count();
+                    // Each `super` moves to the directory holding the parent's submodules
+                    let mut base = module_dir.clone();
+                    for _ in 0..supers {
+                        base = base.parent().unwrap_or(&base).to_path_buf();
+                    }
+                    (base, &segments[supers..], None)
+                }
+                // A binary reaching into its own package's library by name
+                Some((name, rest)) if library.as_ref().is_some_and(|(lib, _)| lib == name) => {
+                    let root = library.as_ref().unwrap().1.clone();
+                    let lib = root.join("lib.rs");
+                    (root, rest, Some(lib))
+                }
+                // Uniform paths: a bare name can be a child module
+                Some(_) => (module_dir.clone(), &segments[..], None),
+                None => continue,
+            };
+            imports.extend(resolve_rust_path(&base, rest).or(root_file));
+        }
+    }
+    imports
+}
+
+fn python_import_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN
+        .get_or_init(|| Regex::new(r"(?m)^\s*import\s+([^\n;]+)").expect("import pattern is valid"))
+}
+
+fn python_from_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?m)^\s*from\s+(\.*)([\w.]*)\s+import\s+(\([^)]*\)|[^\n;]+)")
+            .expect("from-import pattern is valid")
+    })
+}
+
+/// Finds `a.b.c` as `a/b/c.py` or `a/b/c/__init__.py` under `base`.
+fn resolve_python_module(base: &Path, module: &str) -> Option<PathBuf> {
+    let relative: PathBuf = module.split('.').filter(|part| !part.is_empty()).collect();
+    if relative.as_os_str().is_empty() {
+        // `from . import x` names the package itself
+        let init = base.join("__init__.py");
+        return init.is_file().then_some(init);
+    }
+    let file = base.join(&relative).with_extension("py");
+    let package = base.join(&relative).join("__init__.py");
+    [file, package].into_iter().find(|path| path.is_file())
+}
+
+/// Imported names, without `as` aliases or the parentheses of a multi-line import.
+fn python_names(list: &str) -> Vec<&str> {
+    list.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+        .split(',')
+        .filter_map(|item| item.split_whitespace().next())
+        .filter(|name| *name != "*")
+        .collect()
+}
+
+fn python_imports(file: &Path, contents: &str, directory: &Path) -> Vec<PathBuf> {
+    // Docstrings and comments often contain example imports
+    let contents = strip_comments(contents, "python");
+    let file_dir = file.parent().unwrap_or(Path::new(""));
+    // Absolute imports resolve against the search root, a `src/` layout, or any package
+    // directory between the root and the file
+    let mut bases: Vec<PathBuf> = vec![directory.to_path_buf(), directory.join("src")];
+    bases.extend(
+        file_dir
+            .ancestors()
+            .take_while(|dir| dir.starts_with(directory))
+            .map(Path::to_path_buf),
+    );
+    let resolve_absolute = |module: &str| {
+        bases
+            .iter()
+            .find_map(|base| resolve_python_module(base, module))
+    };
+
+    let mut imports = Vec::new();
+    for captures in python_import_pattern().captures_iter(&contents) {
+        for module in python_names(&captures[1]) {
+            imports.extend(resolve_absolute(module));
+        }
+    }
+    for captures in python_from_pattern().captures_iter(&contents) {
+        let (dots, module) = (captures[1].len(), &captures[2]);
+        let names = python_names(&captures[3]);
+        if dots == 0 {
+            imports.extend(resolve_absolute(module));
+            for name in names {
+                imports.extend(resolve_absolute(&format!("{}.{}", module, name)));
+            }
+            continue;
+        }
+        // One dot is the file's own package, each further dot a parent
+        let Some(base) = file_dir.ancestors().nth(dots - 1) else {
+            continue;
+        };
+        imports.extend(resolve_python_module(base, module));
+        for name in names {
+            imports.extend(resolve_python_module(base, &format!("{}.{}", module, name)));
+        }
+    }
+    imports
+}
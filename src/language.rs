@@ -0,0 +1,182 @@
+use globset::GlobMatcher;
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+
+use crate::search::build_glob;
+
+/// Maps a file extension to the language hint used on Markdown code fences.
+fn language_for_extension(ext: &str) -> &'static str {
+    match ext.trim_start_matches('.') {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "xml" => "xml",
+        "sql" => "sql",
+        "md" | "mdx" => "markdown",
+        "dockerfile" => "dockerfile",
+        "mk" => "makefile",
+        "cmake" => "cmake",
+        "gradle" | "groovy" => "groovy",
+        "pl" | "pm" => "perl",
+        "lua" => "lua",
+        "r" => "r",
+        "scala" => "scala",
+        "ex" | "exs" => "elixir",
+        "erl" => "erlang",
+        "hs" => "haskell",
+        "dart" => "dart",
+        "vue" => "vue",
+        "svelte" => "svelte",
+        "tf" => "hcl",
+        "ps1" => "powershell",
+        "ini" | "cfg" => "ini",
+        _ => "",
+    }
+}
+
+/// Files whose name alone identifies their language.
+fn language_for_file_name(name: &str) -> Option<&'static str> {
+    let language = match name {
+        "Dockerfile" | "Containerfile" => "dockerfile",
+        "Makefile" | "GNUmakefile" | "makefile" => "makefile",
+        "CMakeLists.txt" => "cmake",
+        "Gemfile" | "Rakefile" | "Vagrantfile" | "Podfile" => "ruby",
+        "Jenkinsfile" => "groovy",
+        "justfile" | "Justfile" => "just",
+        ".bashrc" | ".bash_profile" | ".profile" => "bash",
+        ".zshrc" => "zsh",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Maps the interpreter named on a `#!` line, e.g. `#!/usr/bin/env python3`, to a language.
+fn language_for_shebang(first_line: &str) -> Option<&'static str> {
+    let command = first_line.strip_prefix("#!")?;
+    let mut words = command.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Skip `env` flags such as `-S`
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    let interpreter = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let language = match interpreter {
+        "python" => "python",
+        "node" | "nodejs" => "javascript",
+        "deno" | "ts-node" | "bun" => "typescript",
+        "sh" | "bash" | "dash" | "ksh" => "bash",
+        "zsh" => "zsh",
+        "fish" => "fish",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "php" => "php",
+        "lua" => "lua",
+        "Rscript" => "r",
+        "pwsh" => "powershell",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Picks the language hint for a file from its name, its extension, and, for scripts without
+/// a telling extension, the interpreter on its `#!` line. `None` means plain text.
+pub fn detect_language(path: &Path, first_line: Option<&str>) -> Option<String> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy());
+    if let Some(language) = file_name.as_deref().and_then(language_for_file_name) {
+        return Some(language.to_string());
+    }
+    let by_extension = path
+        .extension()
+        .map(|ext| language_for_extension(&ext.to_string_lossy().to_lowercase()))
+        .filter(|language| !language.is_empty());
+    by_extension
+        .or_else(|| first_line.and_then(language_for_shebang))
+        .map(str::to_string)
+}
+
+/// Converts a linguist language name such as `C++` into the tag used on code fences.
+fn fence_tag_for_linguist(name: &str) -> String {
+    match name.to_lowercase().as_str() {
+        "c++" => "cpp".to_string(),
+        "c#" => "csharp".to_string(),
+        "f#" => "fsharp".to_string(),
+        "objective-c" => "objectivec".to_string(),
+        "shell" => "bash".to_string(),
+        other => other.replace(' ', "-"),
+    }
+}
+
+/// `linguist-language` overrides from a `.gitattributes` file in the repository root.
+pub(crate) struct LanguageOverrides {
+    /// Patterns in file order; later matches win, as in git
+    pub(crate) rules: Vec<(GlobMatcher, String)>,
+}
+
+pub(crate) const GITATTRIBUTES_FILE_NAME: &str = ".gitattributes";
+
+impl LanguageOverrides {
+    pub(crate) fn load(root: &Path) -> Option<Self> {
+        let path = root.join(GITATTRIBUTES_FILE_NAME);
+        let contents = fs::read_to_string(&path).ok()?;
+        let mut rules = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next() else {
+                continue;
+            };
+            let Some(language) = fields.find_map(|attr| attr.strip_prefix("linguist-language="))
+            else {
+                continue;
+            };
+            // As in .gitignore, a pattern without a slash matches the name at any depth
+            let glob = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{}", pattern),
+            };
+            match build_glob(&glob, false) {
+                Ok(glob) => rules.push((glob.compile_matcher(), fence_tag_for_linguist(language))),
+                Err(e) => warn!("Ignoring pattern in {}: {}", path.display(), e),
+            }
+        }
+        if rules.is_empty() {
+            return None;
+        }
+        info!("Loaded language overrides from {}", path.display());
+        Some(LanguageOverrides { rules })
+    }
+
+    /// The overriding language for a path relative to the repository root, if any.
+    pub(crate) fn language_for(&self, relative_path: &Path) -> Option<String> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.is_match(relative_path))
+            .map(|(_, language)| language.clone())
+    }
+}
@@ -0,0 +1,336 @@
+//! Build a single LLM-ready document from the files in a directory tree or a downloaded
+//! GitHub, GitLab, or Bitbucket repository.
+//!
+//! [`ContextBuilder`] is the entry point for most callers:
+//!
+//! ```no_run
+//! use llm_context_builder::{ContextBuilder, OutputFormat, SearchOptions};
+//!
+//! let options = SearchOptions {
+//!     extensions: vec!["rs".to_string()],
+//!     print_contents: true,
+//!     format: OutputFormat::Markdown,
+//!     ..Default::default()
+//! };
+//! let context = ContextBuilder::new(options).path("src").build()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+mod archives;
+mod comments;
+mod content;
+mod error;
+mod git;
+mod imports;
+mod language;
+mod outline;
+mod output;
+mod overview;
+mod repo;
+mod search;
+mod transform;
+mod util;
+
+pub use comments::strip_comments;
+pub use content::{
+    cached_token_count, count_tokens, is_probably_binary, manifest_hash, redact, token_cache_dir,
+    LineEnding,
+};
+pub use error::ContextError;
+pub use git::CommitInfo;
+pub use language::detect_language;
+pub use outline::outline;
+use output::Document;
+pub use output::{
+    print_files, print_preamble, print_preamble_end, split_parts, write_bundle, write_split_output,
+    OutputFormat,
+};
+pub use overview::detect_project_type;
+pub use repo::{
+    build_archive_url, cache_dir_for, check_github_host, download_and_extract_repo,
+    download_compare, download_sparse, extract_archive, extract_archive_file, is_download_error,
+    parse_github_url, parse_repo_url, resolve_default_branch, ArchiveFormat, CacheMode,
+    ChangedFile, Comparison, DownloadOptions, NotFoundError, RepoHost, RepoInfo,
+};
+pub use search::{
+    collect_appended_files, find_files, read_file_list, type_extensions, MatchedFile,
+    SearchOptions, SkipReason, SortOrder, Stats, COMMON_CONFIG_FILES, TYPE_GROUPS,
+};
+pub use transform::apply_transform;
+pub use util::{
+    context_window, expand_path, expand_tilde, expand_vars, format_size, format_tokens,
+    parse_duration, parse_size, MODEL_CONTEXT_WINDOWS,
+};
+
+/// Collects the files under one or more directories into a single document.
+#[derive(Debug, Default)]
+pub struct ContextBuilder {
+    options: SearchOptions,
+    /// `(label, root, directory)`; files are searched in `directory` and named relative to
+    /// `root`, under a `label` heading when there are several sources
+    sources: Vec<(String, PathBuf, PathBuf)>,
+    /// An explicit file list that replaces the directory walk
+    files: Option<Vec<MatchedFile>>,
+    preamble: Option<String>,
+    /// Printed after everything else, with contents and regardless of the search filters
+    appended: Vec<MatchedFile>,
+    bundle: Option<PathBuf>,
+    split_output: Option<PathBuf>,
+    /// Further documents written from the same matched files, each in its own format
+    outputs: Vec<(OutputFormat, PathBuf)>,
+    /// Canonical paths of files never to include, such as the output file itself
+    skipped: HashSet<PathBuf>,
+}
+
+impl ContextBuilder {
+    pub fn new(options: SearchOptions) -> Self {
+        ContextBuilder {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a directory to search, naming files relative to it.
+    pub fn path(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.source(path.clone(), path)
+    }
+
+    /// Adds `directory` to search while naming files relative to `root`, e.g. a subfolder of
+    /// an extracted repository.
+    pub fn source(self, root: impl Into<PathBuf>, directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        let label = directory.display().to_string();
+        self.labeled_source(label, root, directory)
+    }
+
+    /// Like [`source`](Self::source), but headed with `label` (e.g. the repository URL)
+    /// instead of the directory path when there are several sources.
+    pub fn labeled_source(
+        mut self,
+        label: impl Into<String>,
+        root: impl Into<PathBuf>,
+        directory: impl Into<PathBuf>,
+    ) -> Self {
+        self.sources
+            .push((label.into(), root.into(), directory.into()));
+        self
+    }
+
+    /// Prints exactly these files, in order, before any searched directories.
+    pub fn files(mut self, files: Vec<MatchedFile>) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    /// Text written once before any file contents.
+    pub fn preamble(mut self, preamble: impl Into<String>) -> Self {
+        self.preamble = Some(preamble.into());
+        self
+    }
+
+    /// Prints these files, in order, after all the others under an "Appended files" heading,
+    /// e.g. prompt instructions to follow the code. Their contents are printed even without
+    /// `print_contents`, and the search filters don't apply to them.
+    pub fn append_files(mut self, files: Vec<MatchedFile>) -> Self {
+        self.appended = files;
+        self
+    }
+
+    /// Also packages the matched files into a zip archive at `path`, processed like their
+    /// printed contents.
+    pub fn bundle(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bundle = Some(path.into());
+        self
+    }
+
+    /// Also copies the matched files into the directory `dir`, keeping their relative paths.
+    pub fn split_output(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.split_output = Some(dir.into());
+        self
+    }
+
+    /// Also writes the document in `format` to the file at `path`. The files are matched
+    /// once and every output is formatted from the same results.
+    pub fn output(mut self, format: OutputFormat, path: impl Into<PathBuf>) -> Self {
+        self.outputs.push((format, path.into()));
+        self
+    }
+
+    /// Leaves this file out even when it matches, e.g. an output file inside a searched
+    /// directory. Files that don't exist are ignored.
+    pub fn skip_file(mut self, path: impl AsRef<Path>) -> Self {
+        if let Ok(canonical) = std::fs::canonicalize(path) {
+            self.skipped.insert(canonical);
+        }
+        self
+    }
+
+    /// Writes the document to `out` and returns statistics about the files it contains.
+    pub fn write_to(&self, out: &mut dyn Write) -> Result<Stats, ContextError> {
+        let options = &self.options;
+        let mut files = Vec::new();
+        for (_, path) in &self.outputs {
+            let file = File::create(path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Failed to create output file {}: {}", path.display(), e),
+                )
+            })?;
+            files.push(BufWriter::new(file));
+        }
+        // A preamble is part of the generated context, so a dry run leaves it out
+        let preamble = self.preamble.as_deref().filter(|_| !options.dry_run);
+        // The main document comes first; its options also drive progress and colors
+        let mut documents = vec![(options.clone(), Document::start(out, options, preamble)?)];
+        for ((format, _), file) in self.outputs.iter().zip(&mut files) {
+            let options = SearchOptions {
+                format: *format,
+                progress: false,
+                color: false,
+                ..options.clone()
+            };
+            let document = Document::start(file, &options, preamble)?;
+            documents.push((options, document));
+        }
+
+        let mut seen = self.skipped.clone();
+        // The extra outputs exist now and must not end up in their own context
+        seen.extend(
+            self.outputs
+                .iter()
+                .filter_map(|(_, path)| std::fs::canonicalize(path).ok()),
+        );
+        let mut stats = Stats::default();
+        // Every included file, named as it appears in a bundle
+        let mut included: Vec<(PathBuf, MatchedFile)> = Vec::new();
+        // Files found inside archives must stay extracted until they are hashed and bundled
+        let mut extractions = Vec::new();
+        if let Some(files) = &self.files {
+            let mut files = if options.exclude_empty {
+                search::skip_empty(files.clone(), &mut stats)
+            } else {
+                files.clone()
+            };
+            if let Some(command) = &options.filter_command {
+                files = search::filter_with_command(files, command)?;
+            }
+            for file in &files {
+                stats.record(file);
+            }
+            write_pass(&mut documents, &files, Path::new(""), None, &[], &mut stats)?;
+            included.extend(
+                files
+                    .into_iter()
+                    .map(|file| (file.display_path.clone(), file)),
+            );
+        }
+        for (label, root, directory) in &self.sources {
+            let group = (self.sources.len() > 1).then_some(label.as_str());
+            let stubbed = stats.ignored_dirs.len();
+            let matched = find_files(directory, root, options, &mut seen, &mut stats)?;
+            let ignored = stats.ignored_dirs[stubbed..].to_vec();
+            write_pass(&mut documents, &matched, root, group, &ignored, &mut stats)?;
+            // Several roots would otherwise collide on paths like `src/main.rs`
+            let prefix = match root.file_name() {
+                Some(name) if self.sources.len() > 1 => PathBuf::from(name),
+                _ => PathBuf::new(),
+            };
+            extractions.extend(matched.iter().filter_map(|file| file.extraction.clone()));
+            included.extend(
+                matched
+                    .into_iter()
+                    .map(|file| (prefix.join(&file.display_path), file)),
+            );
+        }
+        if !self.appended.is_empty() {
+            for file in &self.appended {
+                stats.record(file);
+            }
+            for (options, document) in &mut documents {
+                output::print_appendix_header(document, options.format)?;
+                // Only the contents are wanted, not another tree or overview of them
+                *options = SearchOptions {
+                    print_contents: true,
+                    tree: false,
+                    overview: false,
+                    info_header: false,
+                    flatten: false,
+                    ..options.clone()
+                };
+            }
+            write_pass(
+                &mut documents,
+                &self.appended,
+                Path::new(""),
+                None,
+                &[],
+                &mut stats,
+            )?;
+        }
+        for (_, document) in documents {
+            document.finish()?;
+        }
+
+        if options.manifest_hash {
+            let sources: Vec<(PathBuf, PathBuf)> = included
+                .iter()
+                .map(|(name, file)| (name.clone(), file.path.clone()))
+                .collect();
+            stats.manifest_hash = Some(manifest_hash(&sources));
+        }
+        if let Some(path) = &self.bundle {
+            stats.bundled = write_bundle(path, &included, options)?;
+        }
+        if let Some(dir) = &self.split_output {
+            stats.split = write_split_output(dir, &included, options)?;
+        }
+        drop(extractions);
+        if options.fail_on_read_error && !stats.read_errors.is_empty() {
+            let paths: Vec<String> = stats
+                .read_errors
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(ContextError::Io(io::Error::other(format!(
+                "Could not read {} matched file(s): {}",
+                paths.len(),
+                paths.join(", ")
+            ))));
+        }
+        Ok(stats)
+    }
+
+    /// Builds the document in memory.
+    pub fn build(&self) -> Result<String, ContextError> {
+        let mut buffer = Vec::new();
+        self.write_to(&mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Writes one pass of `files` to every document, each formatted with its own options, and
+/// adds what went into the main document to `stats`.
+fn write_pass(
+    documents: &mut [(SearchOptions, Document)],
+    files: &[MatchedFile],
+    root: &Path,
+    group: Option<&str>,
+    ignored: &[(PathBuf, usize)],
+    stats: &mut Stats,
+) -> Result<(), ContextError> {
+    for (i, (options, document)) in documents.iter_mut().enumerate() {
+        let printed = output::write_files(files, root, options, document, group, ignored)?;
+        if i == 0 {
+            stats.tokens += printed.tokens;
+            stats.read_errors.extend(printed.read_errors);
+            stats.file_spans.extend(printed.spans);
+        }
+    }
+    Ok(())
+}
@@ -1,232 +1,1604 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use llm_context_builder::{
+    build_archive_url, cache_dir_for, check_github_host, collect_appended_files, context_window,
+    count_tokens, download_and_extract_repo, download_compare, download_sparse, expand_path,
+    expand_tilde, expand_vars, extract_archive_file, format_size, format_tokens, is_download_error,
+    parse_duration, parse_repo_url, parse_size, read_file_list, resolve_default_branch,
+    split_parts, token_cache_dir, type_extensions, ArchiveFormat, CacheMode, ContextBuilder,
+    ContextError, DownloadOptions, LineEnding, NotFoundError, OutputFormat, RepoHost,
+    SearchOptions, SortOrder, COMMON_CONFIG_FILES, MODEL_CONTEXT_WINDOWS, TYPE_GROUPS,
+};
 use log::{error, info, warn};
-use reqwest;
+use notify::{EventKind, RecursiveMode, Watcher};
+use regex::bytes::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{self};
+use std::io::{self, BufWriter, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempfile::TempDir;
-use url::Url;
-use walkdir::WalkDir;
-use zip::ZipArchive;
-use std::error;
 
-/// This script can search for files locally or in a GitHub repository.
+/// This script can search for files locally or in a GitHub, GitLab, or Bitbucket repository.
 /// It can filter by file extensions, ignore specified directories, and optionally print file contents.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXIT_CODES_HELP)]
 struct Args {
-    /// GitHub URL to download and search
-    #[arg(short, long)]
-    github_url: Option<String>,
+    /// Local directories to search
+    #[arg(
+        default_value = ".",
+        conflicts_with_all = ["github_url", "archive"],
+        value_parser = expand_path
+    )]
+    paths: Vec<PathBuf>,
+
+    /// Print the files listed (one per line) in this file, or `-` for stdin, without walking
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["paths", "github_url", "archive"],
+        value_parser = expand_path
+    )]
+    files_from: Option<PathBuf>,
+
+    /// Print this file after everything else, e.g. instructions for the model, regardless of
+    /// the search filters (repeatable)
+    #[arg(long, value_name = "PATH", value_parser = expand_path)]
+    append_file: Vec<PathBuf>,
+
+    /// Print every file under this directory after everything else, like --append-file
+    /// (repeatable)
+    #[arg(long, value_name = "DIR", value_parser = expand_path)]
+    append_dir: Vec<PathBuf>,
+
+    /// GitHub, GitLab, or Bitbucket URL to download and search, or a GitHub shorthand like
+    /// owner/repo@branch:path (repeatable)
+    #[arg(short, long, visible_alias = "github")]
+    github_url: Vec<String>,
+
+    /// For a GitHub URL naming a folder, download only the files under it through the API
+    /// instead of the whole repository archive (one request per file)
+    #[arg(long, requires = "github_url")]
+    sparse: bool,
+
+    /// Zip or tar.gz archive to extract and search, as a local path or an http(s) URL
+    /// (repeatable)
+    #[arg(long, value_name = "URL_OR_PATH")]
+    archive: Vec<String>,
+
+    /// GitHub Enterprise hostname whose URLs are accepted alongside github.com
+    #[arg(long, env = "GH_HOST", value_name = "HOST")]
+    github_host: Option<String>,
+
+    /// Branch, tag, or commit SHA to download, overriding any branch in the URL; with --base,
+    /// the head ref compared against it (also --head-ref, since --head limits bytes per file)
+    #[arg(long = "ref", visible_alias = "head-ref", requires = "github_url")]
+    git_ref: Option<String>,
+
+    /// For a GitHub URL, include only the files changed since this branch, tag, or commit
+    /// (compared with --ref, the URL's branch, or the default branch), downloading just them
+    /// and printing their diff stat first
+    #[arg(
+        long,
+        value_name = "REF",
+        requires = "github_url",
+        conflicts_with = "sparse"
+    )]
+    base: Option<String>,
+
+    /// Access token for private repositories (falls back to GITHUB_TOKEN)
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Always download the repository instead of reusing the cache
+    #[arg(long, conflicts_with = "refresh_cache")]
+    no_cache: bool,
+
+    /// Re-download the repository and overwrite the cached copy
+    #[arg(long)]
+    refresh_cache: bool,
+
+    /// Extract downloads under this directory instead of the system temp directory (skips the cache)
+    #[arg(long, value_name = "PATH", value_parser = expand_path)]
+    download_dir: Option<PathBuf>,
+
+    /// Keep an uncached download after the run instead of deleting it
+    #[arg(long)]
+    keep_download: bool,
+
+    /// Retry downloads (archives, --sparse files, --base comparisons) that time out, lose the
+    /// connection, or get a 5xx or 429 response this many times, backing off exponentially
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Archive format to download (detected from the response when not given)
+    #[arg(long, value_enum)]
+    archive_format: Option<ArchiveFormat>,
+
+    /// Buffer downloaded archives in a temporary file instead of memory (for very large repositories)
+    #[arg(long)]
+    spill_to_disk: bool,
+
+    /// Keep an interrupted download in the cache directory, keyed by repository URL, ref, and
+    /// ETag, and continue it with an HTTP range request on the next attempt or run, when the
+    /// server supports it
+    #[arg(long)]
+    resume: bool,
 
     /// List of file extensions to search for
     #[arg(short, long, value_delimiter = ' ', num_args = 1..)]
     extensions: Vec<String>,
 
+    /// Exact file name to include regardless of extension, e.g. Dockerfile (repeatable)
+    #[arg(long, value_name = "NAME")]
+    filenames: Vec<String>,
+
+    /// Add the extensions of a named group: code, docs, config, or web (repeatable)
+    #[arg(long = "type", value_name = "GROUP")]
+    types: Vec<String>,
+
+    /// Include common extensionless build and config files (Dockerfile, Makefile, .env.example, ...)
+    #[arg(long)]
+    common_config: bool,
+
+    /// Match --extensions case-sensitively (by default `txt` also matches `.TXT`)
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// Glob of files to include, relative to the search root (repeatable)
+    #[arg(long, value_parser = expand_tilde)]
+    include: Vec<String>,
+
+    /// Glob of files to exclude, relative to the search root (repeatable)
+    #[arg(long, value_parser = expand_tilde)]
+    exclude: Vec<String>,
+
     /// List of directories to ignore
-    #[arg(short, long, value_delimiter = ' ', num_args = 1..)]
+    #[arg(short, long, value_delimiter = ' ', num_args = 1.., value_parser = expand_vars)]
     ignored_dirs: Vec<String>,
 
-    /// List of files to ignore
-    #[arg(short, long, value_delimiter = ' ', num_args = 1..)]
+    /// Only search these top-level directories of each search root, leaving out everything
+    /// else including files in the root itself
+    #[arg(long, value_name = "NAME", value_delimiter = ' ', num_args = 1..)]
+    only_dirs: Vec<String>,
+
+    /// File names or globs to ignore, matched against the name or path relative to the root
+    #[arg(long, value_delimiter = ' ', num_args = 1.., value_parser = expand_tilde)]
     ignored_files: Vec<String>,
 
+    /// Only descend this many directories below the search root (0 = root files only)
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Print a one-line stub for each directory skipped by --ignored-dirs, with its file count
+    #[arg(long)]
+    stub_ignored: bool,
+
+    /// Follow symbolic links to files and directories while walking
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Also include the local files that matched Rust (`mod`/`use`) and Python files import,
+    /// transitively, even when they don't match the filters themselves
+    #[arg(long)]
+    follow_imports: bool,
+
+    /// Only follow imports this many hops from a matched file (1 = direct imports only); not
+    /// --max-depth, which already limits how deep the directory walk goes
+    #[arg(long, requires = "follow_imports")]
+    max_import_depth: Option<usize>,
+
+    /// Search inside .zip and .jar files too, applying the same filters to their entries
+    #[arg(long)]
+    descend_archives: bool,
+
+    /// Skip files excluded by .gitignore, .git/info/exclude, and the global gitignore
+    #[arg(long)]
+    respect_gitignore: bool,
+
+    /// Only include files modified within this window, e.g. 2d or 3h (local searches only)
+    #[arg(long, value_parser = parse_duration)]
+    since: Option<Duration>,
+
+    /// Skip files larger than this size (accepts suffixes like 500k or 2M)
+    #[arg(long, value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Order files by path, size (largest first), mtime (newest first), or ext
+    #[arg(long, value_enum, default_value_t = SortOrder::Path)]
+    sort: SortOrder,
+
+    /// Reverse the --sort order
+    #[arg(long)]
+    reverse: bool,
+
+    /// Only include files whose contents match this regex
+    #[arg(long)]
+    content_match: Option<Regex>,
+
+    /// Skip files whose contents match this regex
+    #[arg(long)]
+    content_exclude: Option<Regex>,
+
+    /// Shell command that receives the matched paths on stdin, one per line, and prints the
+    /// ones to keep
+    #[arg(long, value_name = "CMD")]
+    filter_command: Option<String>,
+
+    /// Keep only files with uncommitted changes in git (modified, staged, or untracked), after
+    /// the other filters; the search paths must be inside a git working tree
+    #[arg(long, conflicts_with_all = ["github_url", "archive", "files_from"])]
+    only_changed: bool,
+
     /// Flag to print file contents
     #[arg(short, long)]
     print_contents: bool,
 
+    /// Skip files that are not valid UTF-8 instead of printing them with replacement characters
+    #[arg(long)]
+    strict_utf8: bool,
+
+    /// Print binary files base64-encoded instead of skipping them
+    #[arg(long)]
+    include_binary: bool,
+
+    /// Print a directory tree of the matched files before their contents
+    #[arg(long)]
+    tree: bool,
+
+    /// Name files by their base name only, numbering duplicates, to hide the directory layout
+    #[arg(long)]
+    flatten: bool,
+
+    /// Show the last commit's hash, author, and date in each file header (local git repositories)
+    #[arg(long)]
+    with_git_info: bool,
+
+    /// Show each file's size in its header, with its token count when tokens are counted,
+    /// e.g. `# File: src/main.rs (4.2KB, 1203 tokens)`
+    #[arg(long)]
+    annotate_headers: bool,
+
+    /// Print a summary of the project type and line counts per language before the contents
+    #[arg(long)]
+    overview: bool,
+
+    /// Start the output with a fenced JSON block listing the files, their sizes, and estimated
+    /// tokens (plain and markdown formats)
+    #[arg(long)]
+    info_header: bool,
+
+    /// List matched files and their sizes without reading or printing contents
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print only the number of matched files, for scripts
+    #[arg(long, conflicts_with_all = ["dry_run", "output", "clipboard", "bundle", "split_output"])]
+    count_only: bool,
+
+    /// Exit with an error, after trying every file, when any matched file can't be read
+    #[arg(long)]
+    fail_on_read_error: bool,
+
+    /// Exit with code 3 when no files match; without it an empty result still exits with 0
+    #[arg(long)]
+    fail_on_empty: bool,
+
+    /// Text printed once before any file contents, e.g. instructions for the model
+    #[arg(long, conflicts_with = "preamble_file")]
+    preamble: Option<String>,
+
+    /// Read the preamble text from this file
+    #[arg(long, value_parser = expand_path)]
+    preamble_file: Option<PathBuf>,
+
+    /// Pick which matched files to include from a checklist before printing
+    #[arg(long)]
+    interactive: bool,
+
+    /// Print identical files once and reference the first copy for the rest
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Skip zero-byte and whitespace-only files, such as empty `__init__.py` markers
+    #[arg(long)]
+    exclude_empty: bool,
+
+    /// Replace API keys, tokens, and private keys with ***REDACTED*** before printing
+    #[arg(long)]
+    redact_secrets: bool,
+
+    /// Remove comments and docstrings (Rust, Python, JavaScript, C-family, shell, ...) to
+    /// save tokens; with --count-tokens the savings are reported
+    #[arg(long)]
+    strip_comments: bool,
+
+    /// Print only declarations (functions, structs, classes, ...) with function bodies
+    /// elided, for Rust, Python, JavaScript/TypeScript, Go, Java, C, and C++ files
+    #[arg(long)]
+    outline: bool,
+
+    /// Rewrite line endings in printed contents to LF or CRLF
+    #[arg(long, value_enum, default_value_t = LineEnding::Lf)]
+    normalize_eol: LineEnding,
+
+    /// Print line endings exactly as they are in each file
+    #[arg(long, conflicts_with = "normalize_eol")]
+    no_normalize: bool,
+
+    /// Prefix each printed line with its line number
+    #[arg(long)]
+    line_numbers: bool,
+
+    /// Print only the first and last N/2 lines of files longer than N lines
+    #[arg(long, value_name = "N")]
+    max_lines_per_file: Option<usize>,
+
+    /// Print at most this many bytes of each file (accepts suffixes like 2k), cut on a character
+    /// boundary and marked `... (truncated)`
+    #[arg(long, value_name = "BYTES", value_parser = parse_size)]
+    head: Option<u64>,
+
+    /// Read large files through a memory map instead of with read calls (if mapping fails,
+    /// files are read normally). The decoded contents are still copied into memory like any
+    /// other file's, so this doesn't lower memory use. A file truncated by another process
+    /// while it is read can crash the run
+    #[arg(long)]
+    mmap: bool,
+
+    /// Smallest file read through a memory map with --mmap (accepts suffixes like 4M)
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = parse_size,
+        default_value = "1M",
+        requires = "mmap"
+    )]
+    mmap_threshold: u64,
+
+    /// Count tokens for each printed file and print a total at the end (logged instead for
+    /// xml and json)
+    #[arg(long)]
+    count_tokens: bool,
+
+    /// Count every file's tokens afresh instead of reusing counts cached from earlier runs
+    #[arg(long)]
+    no_token_cache: bool,
+
+    /// Stop printing file contents once the running token total would exceed this budget
+    #[arg(long)]
+    max_tokens: Option<usize>,
+
+    /// Warn when the output would not fit this model's context window (implies --count-tokens)
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Line printed before each file in the plain format; `{path}` is replaced with its path
+    #[arg(long)]
+    header_template: Option<String>,
+
+    /// Line printed after each file in the plain format; `{path}` is replaced with its path
+    #[arg(long)]
+    footer_template: Option<String>,
+
+    /// Output format for printed file contents [default: plain]. Repeat it with one --output
+    /// per format to write several formats from a single search
+    #[arg(id = "format", long = "format", value_enum)]
+    formats: Vec<OutputFormat>,
+
+    /// The first --format, or the one from .llmcontext.toml
+    #[arg(skip)]
+    format: Option<OutputFormat>,
+
+    /// Number of threads used to read files (defaults to the number of logical cores)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Number of threads writing files out of a zip archive; more than one keeps the whole
+    /// archive in memory while extracting
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    extract_jobs: usize,
+
+    /// Number of files read ahead of the writer, bounding memory use [default: 64]
+    #[arg(long, value_name = "FILES")]
+    buffer_size: Option<usize>,
+
+    /// Also package the matched files into this zip archive, keeping their relative paths and
+    /// processing their contents exactly as when printed, like --split-output
+    #[arg(long, value_name = "PATH.zip", value_parser = expand_path)]
+    bundle: Option<PathBuf>,
+
+    /// Also copy each matched file into this directory, keeping relative paths and processing
+    /// its contents exactly as when printed (transforms, --strip-comments, --outline,
+    /// --redact-secrets, --line-numbers, --max-lines-per-file, ...)
+    #[arg(long, value_name = "DIR", value_parser = expand_path)]
+    split_output: Option<PathBuf>,
+
+    /// Split the output into parts of at most N tokens without breaking up any file, written
+    /// next to --output as name.part1.md, name.part2.md, ... (plain and markdown formats)
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "output",
+        conflicts_with_all = ["clipboard", "watch"]
+    )]
+    chunk_tokens: Option<usize>,
+
+    /// Copy the output to the system clipboard instead of printing it
+    #[arg(long)]
+    clipboard: bool,
+
+    /// Print a summary of matched files, sizes per extension, and the largest files to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Print a hash of the included file names and contents to stderr, to detect when the context changed
+    #[arg(long)]
+    print_manifest_hash: bool,
+
+    /// Write output to this file instead of stdout (repeatable, paired with each --format)
+    #[arg(id = "output", short = 'o', long = "output", value_parser = expand_path)]
+    outputs: Vec<PathBuf>,
+
+    /// The first --output
+    #[arg(skip)]
+    output: Option<PathBuf>,
+
+    /// The --format and --output pairs after the first
+    #[arg(skip)]
+    extra_outputs: Vec<(OutputFormat, PathBuf)>,
+
+    /// Keep running and rewrite --output whenever a file under the search paths changes
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with_all = ["github_url", "archive", "files_from", "clipboard", "interactive"]
+    )]
+    watch: bool,
+
+    /// Also write warnings and skipped-file events to this file as newline-delimited JSON
+    #[arg(long, value_name = "PATH", value_parser = expand_path)]
+    log_json: Option<PathBuf>,
+
     /// Increase output verbosity
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
+
+    /// Log every skipped file with the reason it was left out, whatever the verbosity
+    #[arg(long)]
+    verbose_skip: bool,
+
+    /// Print a completion script for this shell and exit
+    #[arg(long, hide = true, value_name = "SHELL")]
+    completions: Option<clap_complete::Shell>,
+
+    /// Only log errors, overriding --verbose, and hide progress and summary messages
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Content transforms by extension, only set from the config file
+    #[arg(skip)]
+    transforms: BTreeMap<String, String>,
 }
 
-#[derive(Debug)]
-struct GithubInfo {
-    repo_url: String,
-    branch_name: Option<String>,
-    folder_path: Option<String>,
+const CONFIG_FILE_NAME: &str = ".llmcontext.toml";
+
+/// Defaults read from `.llmcontext.toml`; any value given on the command line wins.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    extensions: Vec<String>,
+    filenames: Vec<String>,
+    ignored_dirs: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    format: Option<OutputFormat>,
+    /// Extension to a built-in transform or shell command, e.g. `json = "jq ."`
+    transforms: BTreeMap<String, String>,
+}
+
+fn load_config() -> Option<Config> {
+    let contents = match fs::read_to_string(CONFIG_FILE_NAME) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Could not read {}: {}", CONFIG_FILE_NAME, e);
+            return None;
+        }
+    };
+    let config = toml::from_str::<Config>(&contents)
+        .map_err(|e| e.to_string())
+        .and_then(Config::expand);
+    match config {
+        Ok(config) => {
+            info!("Loaded configuration from {}", CONFIG_FILE_NAME);
+            Some(config)
+        }
+        Err(e) => {
+            warn!("Ignoring invalid {}: {}", CONFIG_FILE_NAME, e);
+            None
+        }
+    }
+}
+
+impl Config {
+    /// Applies the same `~` and `$VAR` expansion as the matching command line flags.
+    fn expand(mut self) -> Result<Self, String> {
+        self.ignored_dirs = expand_all(self.ignored_dirs, expand_vars)?;
+        self.include = expand_all(self.include, expand_tilde)?;
+        self.exclude = expand_all(self.exclude, expand_tilde)?;
+        // Looked up by lowercase extension, so `.JSON` and `json` are the same key
+        self.transforms = self
+            .transforms
+            .into_iter()
+            .map(|(ext, transform)| (ext.trim_start_matches('.').to_lowercase(), transform))
+            .collect();
+        Ok(self)
+    }
+}
+
+fn expand_all(
+    values: Vec<String>,
+    expand: fn(&str) -> Result<String, String>,
+) -> Result<Vec<String>, String> {
+    values.iter().map(|value| expand(value)).collect()
+}
+
+impl Args {
+    /// Pairs each `--format` with the `--output` in the same position. The first pair is the
+    /// main output; the others are written from the same matched files.
+    fn pair_outputs(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let formats = std::mem::take(&mut self.formats);
+        let outputs = std::mem::take(&mut self.outputs);
+        if formats.len() > 1 || outputs.len() > 1 {
+            if formats.len() != outputs.len() {
+                return Err(format!(
+                    "Give one --output for each --format ({} --format and {} --output given)",
+                    formats.len(),
+                    outputs.len()
+                )
+                .into());
+            }
+            if self.clipboard || self.chunk_tokens.is_some() || self.watch {
+                return Err(
+                    "Several --format/--output pairs can't be combined with --clipboard, --chunk-tokens, or --watch"
+                        .into(),
+                );
+            }
+        }
+        let (mut formats, mut outputs) = (formats.into_iter(), outputs.into_iter());
+        self.format = formats.next();
+        self.output = outputs.next();
+        self.extra_outputs = formats.zip(outputs).collect();
+        Ok(())
+    }
+
+    fn merge_config(&mut self, config: Config) {
+        if self.extensions.is_empty() {
+            self.extensions = config.extensions;
+        }
+        if self.filenames.is_empty() {
+            self.filenames = config.filenames;
+        }
+        if self.ignored_dirs.is_empty() {
+            self.ignored_dirs = config.ignored_dirs;
+        }
+        if self.include.is_empty() {
+            self.include = config.include;
+        }
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude;
+        }
+        if self.format.is_none() {
+            self.format = config.format;
+        }
+        self.transforms = config.transforms;
+    }
+}
+
+/// Forwards records to the console logger and, with `--log-json`, also writes them as
+/// newline-delimited JSON. Records carrying an `event` key (such as skipped files) are always
+/// written; other records only at warning level and above.
+struct Logger {
+    console: env_logger::Logger,
+    /// For `--verbose-skip`, a console logger that lets every level through, used only for
+    /// skipped-file events
+    skips: Option<env_logger::Logger>,
+    json: Option<Mutex<File>>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.json.is_some() || self.skips.is_some() || self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        let mut event = JsonEvent::default();
+        let _ = record.key_values().visit(&mut event);
+        if self.console.matches(record) {
+            self.console.log(record);
+        } else if let Some(skips) = &self.skips {
+            if event.fields.get("event").and_then(|e| e.as_str()) == Some("skipped") {
+                skips.log(record);
+            }
+        }
+        let Some(json) = &self.json else {
+            return;
+        };
+        if !event.fields.contains_key("event") {
+            if record.level() > log::Level::Warn {
+                return;
+            }
+            event.fields.insert(
+                "event".to_string(),
+                record.level().as_str().to_lowercase().into(),
+            );
+        }
+        event
+            .fields
+            .insert("level".to_string(), record.level().as_str().into());
+        event
+            .fields
+            .insert("message".to_string(), record.args().to_string().into());
+        if let Ok(mut line) = serde_json::to_vec(&event.fields) {
+            line.push(b'\n');
+            // One write per event keeps lines whole when several threads log at once
+            let _ = json.lock().unwrap().write_all(&line);
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+#[derive(Default)]
+struct JsonEvent {
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonEvent {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = match value.to_u64() {
+            Some(number) => number.into(),
+            None => value.to_string().into(),
+        };
+        self.fields.insert(key.to_string(), value);
+        Ok(())
+    }
 }
 
-fn setup_logging(verbosity: u8) {
+fn setup_logging(
+    verbosity: u8,
+    quiet: bool,
+    verbose_skip: bool,
+    log_json: Option<&Path>,
+) -> Result<(), Box<dyn error::Error>> {
     let level = match verbosity {
+        _ if quiet => log::LevelFilter::Error,
         0 => log::LevelFilter::Warn,
         1 => log::LevelFilter::Info,
         _ => log::LevelFilter::Debug,
     };
 
-    env_logger::Builder::new()
+    let console = env_logger::Builder::new()
         .format_timestamp_secs()
         .filter_level(level)
-        .init();
+        .build();
+    let skips = verbose_skip.then(|| {
+        env_logger::Builder::new()
+            .format_timestamp_secs()
+            .filter_level(log::LevelFilter::Debug)
+            .build()
+    });
+    let json = match log_json {
+        Some(path) => Some(Mutex::new(File::create(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to create log file {}: {}", path.display(), e),
+            )
+        })?)),
+        None => None,
+    };
+    // Skip decisions are logged down to debug level, and the JSON log wants all of them
+    let max_level = if json.is_some() || skips.is_some() {
+        log::LevelFilter::Debug.max(level)
+    } else {
+        level
+    };
+    log::set_boxed_logger(Box::new(Logger {
+        console,
+        skips,
+        json,
+    }))?;
+    log::set_max_level(max_level);
+    Ok(())
 }
 
-fn parse_github_url(url: &str) -> Result<GithubInfo, Box<dyn error::Error>> {
-    let parsed_url = Url::parse(url)?;
+fn copy_to_clipboard(contents: &[u8]) -> Result<(), Box<dyn error::Error>> {
+    let text = String::from_utf8_lossy(contents);
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|e| format!("Failed to copy to clipboard: {}", e).into())
+}
 
-    if parsed_url.host_str() != Some("github.com") {
-        return Err("Not a valid GitHub URL".into());
-    }
+/// A downloaded repository ready to search.
+struct Checkout {
+    root: PathBuf,
+    search_path: PathBuf,
+    /// With --base, the changed files' diff stat
+    diff_stat: Option<String>,
+    /// An uncached extraction, deleted when the checkout is dropped
+    _extraction: Option<TempDir>,
+}
 
-    let path_segments: Vec<&str> = parsed_url
-        .path_segments()
-        .ok_or("No path segments")?
-        .collect();
+/// Creates a temporary directory for an uncached extraction, under --download-dir if given.
+fn extraction_dir(args: &Args) -> Result<TempDir, Box<dyn error::Error>> {
+    tempfile::Builder::new()
+        .prefix("llm-context-")
+        .tempdir_in(args.download_dir.clone().unwrap_or_else(env::temp_dir))
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to create a download directory: {}", e),
+            )
+            .into()
+        })
+}
 
-    if path_segments.len() < 2 {
-        return Err("URL doesn't contain a valid repository path".into());
+/// With --keep-download, turns `dir` into a permanent directory and returns `None`.
+fn keep_if_requested(dir: TempDir, args: &Args) -> Option<TempDir> {
+    if !args.keep_download {
+        return Some(dir);
+    }
+    let kept = dir.into_path();
+    if !args.quiet {
+        eprintln!("Kept download in {}", kept.display());
     }
+    None
+}
 
-    let repo_url = format!(
-        "https://github.com/{}/{}",
-        path_segments[0], path_segments[1]
-    );
+/// Downloads (for an http(s) URL) or opens the archive named by --archive and extracts it.
+/// URLs are cached like repositories; local archives are always extracted afresh.
+fn fetch_archive_input(
+    archive: &str,
+    args: &Args,
+    show_progress: bool,
+) -> Result<Checkout, Box<dyn error::Error>> {
+    let is_url = archive.starts_with("https://") || archive.starts_with("http://");
+    let cache_dir = if args.no_cache || args.download_dir.is_some() || !is_url {
+        None
+    } else {
+        cache_dir_for(archive)
+    };
+    let (extraction, target_folder) = match cache_dir {
+        Some(dir) => (None, dir),
+        None => {
+            let dir = extraction_dir(args)?;
+            let path = dir.path().to_path_buf();
+            (Some(dir), path)
+        }
+    };
 
-    let (branch_name, folder_path) = if path_segments.len() >= 4 && path_segments[2] == "tree" {
-        let branch = Some(path_segments[3].to_string());
-        let folder = if path_segments.len() > 4 {
-            Some(path_segments[4..].join("/"))
-        } else {
-            None
+    let root = if is_url {
+        info!("Downloading archive from: {}", archive);
+        let cache_mode = match (&extraction, args.refresh_cache) {
+            (Some(_), _) => CacheMode::Bypass,
+            (None, true) => CacheMode::Refresh,
+            (None, false) => CacheMode::Use,
         };
-        (branch, folder)
+        let download = DownloadOptions {
+            // A code host token must not leak to an arbitrary server
+            token: None,
+            cache_mode,
+            show_progress,
+            spill_to_disk: args.spill_to_disk,
+            archive_format: args.archive_format,
+            retries: args.retries,
+            resume: args.resume,
+            extract_jobs: args.extract_jobs,
+        };
+        download_and_extract_repo(archive, &target_folder, download)?
     } else {
-        (None, None)
+        extract_archive_file(
+            &expand_path(archive)?,
+            args.archive_format,
+            &target_folder,
+            args.extract_jobs,
+        )?
     };
+    info!("Archive extracted to: {}", root.display());
 
-    Ok(GithubInfo {
-        repo_url,
-        branch_name,
-        folder_path,
+    Ok(Checkout {
+        search_path: root.clone(),
+        root,
+        diff_stat: None,
+        _extraction: extraction.and_then(|dir| keep_if_requested(dir, args)),
     })
 }
 
-fn build_zip_url(repo_url: &str, branch: &str) -> String {
-    format!("{}/archive/{}.zip", repo_url, branch)
+/// Whether a download failed because the repository or ref doesn't exist.
+fn is_not_found(err: &ContextError) -> bool {
+    matches!(err, ContextError::Download(e) if e.is::<NotFoundError>())
 }
 
-fn download_and_extract_repo(
-    zip_url: &str,
-    target_folder: &Path,
-) -> Result<PathBuf, Box<dyn error::Error>> {
-    let temp_dir = TempDir::new()?;
-    let zip_path = temp_dir.path().join("repo.zip");
+/// Downloads `github_url` (trying fallback branches when none is given), or returns `None`
+/// when the folder named in the URL is missing from the repository.
+fn fetch_repository(
+    github_url: &str,
+    args: &Args,
+    show_progress: bool,
+) -> Result<Option<Checkout>, Box<dyn error::Error>> {
+    let repo_info = parse_repo_url(github_url, args.github_host.as_deref())?;
+    if args.base.is_some() && repo_info.host != RepoHost::GitHub {
+        return Err("--base only supports GitHub repositories".into());
+    }
+    let token = args.token.as_deref();
+    let branches = match args.git_ref.as_ref().or(repo_info.branch_name.as_ref()) {
+        Some(git_ref) => vec![git_ref.clone()],
+        None => match resolve_default_branch(repo_info.host, &repo_info.repo_url, token) {
+            Ok(branch) => vec![branch],
+            Err(e) => {
+                warn!(
+                    "Could not resolve default branch ({}), trying 'main' then 'master'",
+                    e
+                );
+                vec!["main".to_string(), "master".to_string()]
+            }
+        },
+    };
 
-    // Download zip file
-    let mut response = reqwest::blocking::get(zip_url)?;
-    let mut file = File::create(&zip_path)?;
-    io::copy(&mut response, &mut file)?;
+    // Comparisons are downloaded afresh; their file list depends on both refs
+    let cache_mode = if args.no_cache || args.download_dir.is_some() || args.base.is_some() {
+        CacheMode::Bypass
+    } else if args.refresh_cache {
+        CacheMode::Refresh
+    } else {
+        CacheMode::Use
+    };
 
-    // Extract zip file
-    let zip_file = File::open(&zip_path)?;
-    let mut archive = ZipArchive::new(zip_file)?;
+    // Only GitHub can list a folder's files without downloading the whole repository
+    let sparse_folder = match (&repo_info.folder_path, repo_info.host) {
+        (Some(folder), RepoHost::GitHub) if args.sparse => Some(folder.as_str()),
+        (None, _) if args.sparse => {
+            warn!("--sparse needs a folder in the URL; downloading the whole repository");
+            None
+        }
+        (Some(_), _) if args.sparse => {
+            warn!("--sparse only supports GitHub; downloading the whole repository");
+            None
+        }
+        _ => None,
+    };
+
+    let mut extraction: Option<TempDir> = None;
+    let mut extracted_path = None;
+    let mut diff_stat = None;
+    for (i, branch) in branches.iter().enumerate() {
+        let archive_url = match (&args.base, sparse_folder) {
+            (Some(base), _) => format!("{}/compare/{}...{}", repo_info.repo_url, base, branch),
+            // Cached under the folder's own URL, apart from full archives
+            (None, Some(folder)) => format!("{}/tree/{}/{}", repo_info.repo_url, branch, folder),
+            (None, None) => build_archive_url(
+                repo_info.host,
+                &repo_info.repo_url,
+                branch,
+                args.archive_format.unwrap_or_default(),
+            ),
+        };
+        let (target_folder, cache_mode) = match cache_dir_for(&archive_url) {
+            Some(dir) if cache_mode != CacheMode::Bypass => (dir, cache_mode),
+            _ => {
+                if extraction.is_none() {
+                    extraction = Some(extraction_dir(args)?);
+                }
+                let dir = extraction.as_ref().map(|dir| dir.path().to_path_buf());
+                (dir.unwrap_or_default(), CacheMode::Bypass)
+            }
+        };
+        info!("Downloading repository from: {}", archive_url);
+        let download = DownloadOptions {
+            token,
+            cache_mode,
+            show_progress,
+            spill_to_disk: args.spill_to_disk,
+            archive_format: args.archive_format,
+            retries: args.retries,
+            resume: args.resume,
+            extract_jobs: args.extract_jobs,
+        };
+        let downloaded = match (&args.base, sparse_folder) {
+            (Some(base), _) => download_compare(
+                &repo_info.repo_url,
+                base,
+                branch,
+                repo_info.folder_path.as_deref(),
+                &target_folder,
+                download,
+            )
+            .map(|comparison| {
+                diff_stat = Some(format!(
+                    "Changes in {} from {} to {}:\n{}",
+                    repo_info.repo_url,
+                    base,
+                    branch,
+                    comparison.stat()
+                ));
+                comparison.root
+            }),
+            (None, Some(folder)) => download_sparse(
+                &repo_info.repo_url,
+                branch,
+                folder,
+                &target_folder,
+                download,
+            ),
+            (None, None) => download_and_extract_repo(&archive_url, &target_folder, download),
+        };
+        match downloaded {
+            Ok(path) => {
+                info!("Using ref '{}'", branch);
+                extracted_path = Some(path);
+                break;
+            }
+            Err(e) if is_not_found(&e) && i + 1 < branches.len() => {
+                warn!("{}, trying branch '{}'", e, branches[i + 1]);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let extracted_path = extracted_path.ok_or("No branch could be downloaded")?;
+    info!(
+        "Repository downloaded and extracted to: {}",
+        extracted_path.display()
+    );
+    let extraction = extraction.and_then(|dir| keep_if_requested(dir, args));
 
-    // Create target directory if it doesn't exist
-    fs::create_dir_all(target_folder)?;
+    let search_path = match repo_info.folder_path {
+        Some(folder_path) => extracted_path.join(folder_path),
+        None => extracted_path.clone(),
+    };
+    if !search_path.exists() {
+        warn!(
+            "Specified folder '{}' does not exist in the repository.",
+            search_path.display()
+        );
+        return Ok(None);
+    }
+    Ok(Some(Checkout {
+        root: extracted_path,
+        search_path,
+        diff_stat,
+        _extraction: extraction,
+    }))
+}
 
-    // Extract all files
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let outpath = target_folder.join(file.mangled_name());
+/// How long the search paths must stay quiet after a change before `--watch` rebuilds, so
+/// a burst of saves (or a `git checkout`) causes a single rebuild.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
 
-        if file.name().ends_with('/') {
-            fs::create_dir_all(&outpath)?;
-        } else {
-            if let Some(p) = outpath.parent() {
-                fs::create_dir_all(p)?;
+/// State for `--watch`: what to watch, and which changes to ignore.
+struct Watch<'a> {
+    paths: &'a [PathBuf],
+    output: &'a Path,
+    /// Canonical paths of the files this run writes itself
+    written: Vec<PathBuf>,
+    ignored_dirs: Vec<String>,
+    quiet: bool,
+}
+
+impl Watch<'_> {
+    /// Rewrites the output after every change until the process is interrupted. Failed
+    /// rebuilds are logged and watching continues.
+    fn run(&self, builder: &ContextBuilder) -> Result<(), Box<dyn error::Error>> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for path in self.paths {
+            // Canonical paths make events comparable with `written`
+            watcher.watch(&fs::canonicalize(path)?, RecursiveMode::Recursive)?;
+        }
+        if !self.quiet {
+            eprintln!("Watching for changes (Ctrl-C to stop)");
+        }
+
+        while let Ok(event) = events.recv() {
+            if !self.is_relevant(event) {
+                continue;
+            }
+            while events.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+            match self.rebuild(builder) {
+                Ok((files, tokens)) if !self.quiet => eprintln!(
+                    "[{}] Rebuilt {}: {} files, {} tokens",
+                    clock_time(SystemTime::now()),
+                    self.output.display(),
+                    files,
+                    format_tokens(tokens)
+                ),
+                Ok(_) => {}
+                Err(e) => error!("Rebuilding {} failed: {}", self.output.display(), e),
+            }
+        }
+        Ok(())
+    }
+
+    fn is_relevant(&self, event: notify::Result<notify::Event>) -> bool {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Watch error: {}", e);
+                return false;
             }
-            let mut outfile = File::create(&outpath)?;
-            io::copy(&mut file, &mut outfile)?;
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return false;
         }
+        event.paths.iter().any(|path| {
+            !self.written.iter().any(|written| path.starts_with(written))
+                && !path.components().any(|component| {
+                    component.as_os_str() == ".git"
+                        || self
+                            .ignored_dirs
+                            .iter()
+                            .any(|dir| component.as_os_str() == dir.as_str())
+                })
+        })
     }
 
-    Ok(target_folder.to_path_buf())
+    /// Returns the number of files and the tokens in the new output.
+    fn rebuild(&self, builder: &ContextBuilder) -> Result<(usize, usize), Box<dyn error::Error>> {
+        let file = File::create(self.output).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to create output file {}: {}",
+                    self.output.display(),
+                    e
+                ),
+            )
+        })?;
+        let mut out = BufWriter::new(file);
+        let stats = builder.write_to(&mut out)?;
+        out.flush()?;
+        drop(out);
+        let contents = fs::read(self.output)?;
+        Ok((
+            stats.files,
+            count_tokens(&String::from_utf8_lossy(&contents)),
+        ))
+    }
 }
 
-fn find_files(
-    directory: &Path,
-    extensions: &[String],
-    ignored_dirs: &[String],
-    ignored_files: &[String],
-    print_contents: bool,
-) -> Result<(), Box<dyn error::Error>> {
-    for entry in WalkDir::new(directory).into_iter().filter_entry(|e| {
-        let file_name = e.file_name().to_string_lossy();
-        if e.file_type().is_dir() {
-            !ignored_dirs.contains(&file_name.to_string())
-        } else {
-            !ignored_files.contains(&file_name.to_string())
-        }
-    }) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            let file_path = entry.path();
-            if let Some(extension) = file_path.extension() {
-                if extensions.iter().any(|ext| {
-                    ext.trim_start_matches('.') == extension.to_string_lossy().to_string()
-                }) {
-                    info!("Found file: {}", file_path.display());
-
-                    if print_contents {
-                        match fs::read_to_string(file_path) {
-                            Ok(contents) => {
-                                println!("# File: {}", file_path.display());
-                                println!("{}", contents);
-                                println!("# {}", "-".repeat(50));
-                            }
-                            Err(e) => error!("Error reading file {}: {}", file_path.display(), e),
-                        }
-                    }
-                }
-            }
+/// Names part `n` of a chunked output, e.g. `context.md` → `context.part2.md`.
+fn part_path(output: &Path, n: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}.part{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}.part{}", stem, n),
+    };
+    output.with_file_name(name)
+}
+
+/// Formats a time of day as `HH:MM:SS` UTC.
+fn clock_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// The exit codes below, listed at the end of `--help`.
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success, including when no files matched without --fail-on-empty
+  1  any other error
+  2  invalid command line arguments
+  3  no files matched, with --fail-on-empty
+  4  downloading a repository or archive failed
+  5  reading or writing local files failed";
+
+/// Exit code for failures that have no more specific code. clap exits with 2 on usage errors.
+const EXIT_FAILURE: u8 = 1;
+/// Exit code when nothing matched and --fail-on-empty was given.
+const EXIT_NO_MATCHES: u8 = 3;
+/// Exit code when downloading a repository or archive failed.
+const EXIT_DOWNLOAD: u8 = 4;
+/// Exit code when reading or writing local files failed.
+const EXIT_IO: u8 = 5;
+
+/// An error that carries its own exit code.
+#[derive(Debug)]
+struct ExitError {
+    code: u8,
+    message: String,
+}
+
+impl ExitError {
+    fn new(code: u8, message: impl Into<String>) -> Self {
+        ExitError {
+            code,
+            message: message.into(),
         }
     }
-    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn error::Error>> {
-    let args = Args::parse();
-    setup_logging(args.verbose);
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for ExitError {}
 
-    let search_path = if let Some(github_url) = args.github_url {
-        let github_info = parse_github_url(&github_url)?;
-        let branch_name = github_info
-            .branch_name
-            .unwrap_or_else(|| "main".to_string());
+/// Picks the exit code for `err` from the first error in its chain with a known kind.
+fn exit_code(err: &(dyn error::Error + 'static)) -> u8 {
+    if is_download_error(err) {
+        return EXIT_DOWNLOAD;
+    }
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(err) = err.downcast_ref::<ExitError>() {
+            return err.code;
+        }
+        if err.is::<io::Error>() || matches!(err.downcast_ref(), Some(ContextError::Io(_))) {
+            return EXIT_IO;
+        }
+        current = err.source();
+    }
+    EXIT_FAILURE
+}
 
-        let zip_url = build_zip_url(&github_info.repo_url, &branch_name);
-        info!("Downloading repository from: {}", zip_url);
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(e.as_ref()))
+        }
+    }
+}
 
-        let target_folder = Path::new("downloaded_repo");
-        let extracted_path = download_and_extract_repo(&zip_url, target_folder)?;
-        info!(
-            "Repository downloaded and extracted to: {}",
-            extracted_path.display()
+fn run() -> Result<(), Box<dyn error::Error>> {
+    let mut args = Args::parse();
+    args.pair_outputs()?;
+    if let Some(shell) = args.completions {
+        let mut command = Args::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+        return Ok(());
+    }
+    setup_logging(
+        args.verbose,
+        args.quiet,
+        args.verbose_skip,
+        args.log_json.as_deref(),
+    )?;
+    if let Some(config) = load_config() {
+        args.merge_config(config);
+    }
+
+    if let Some(host) = &args.github_host {
+        args.github_host = Some(check_github_host(host)?);
+    }
+
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+
+    let model_window = match &args.model {
+        Some(model) => Some(context_window(model).ok_or_else(|| {
+            let known: Vec<&str> = MODEL_CONTEXT_WINDOWS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect();
+            format!(
+                "Unknown model '{}', expected one of: {}",
+                model,
+                known.join(", ")
+            )
+        })?),
+        None => None,
+    };
+
+    // Progress bars would interleave with output printed to the terminal or a pipe
+    let show_progress = !args.quiet && args.output.is_some() && io::stdout().is_terminal();
+    // Color codes only help a human reading stdout; files, pipes, and the clipboard stay plain
+    let color = args.output.is_none()
+        && !args.clipboard
+        && io::stdout().is_terminal()
+        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+
+    // JSON and XML documents can't be cut into parts that each stay well-formed
+    let format = args.format.unwrap_or_default();
+    if args.chunk_tokens.is_some()
+        && !matches!(format, OutputFormat::Plain | OutputFormat::Markdown)
+    {
+        return Err("--chunk-tokens only supports the plain and markdown formats".into());
+    }
+
+    if args.since.is_some() && !(args.github_url.is_empty() && args.archive.is_empty()) {
+        warn!(
+            "--since compares modification times, which are reset when a repository is extracted"
         );
+    }
 
-        if let Some(folder_path) = github_info.folder_path {
-            let search_path = extracted_path.join(folder_path);
-            if !search_path.exists() {
-                warn!(
-                    "Specified folder '{}' does not exist in the repository.",
-                    search_path.display()
-                );
-                return Ok(());
+    // Holds temporary extractions until the output has been written
+    let mut checkouts = Vec::new();
+    let sources = if args.files_from.is_some() {
+        Vec::new()
+    } else if !args.github_url.is_empty() || !args.archive.is_empty() {
+        // `(input, is_archive)` for every repository and archive, in the order they are printed
+        let inputs: Vec<(&String, bool)> = (args.github_url.iter().map(|url| (url, false)))
+            .chain(args.archive.iter().map(|archive| (archive, true)))
+            .collect();
+        // Each input downloads on its own thread; progress bars would overwrite each other
+        let show_progress = show_progress && inputs.len() == 1;
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = inputs
+                .iter()
+                .map(|&(input, is_archive)| {
+                    let args = &args;
+                    scope.spawn(move || {
+                        let checkout = if is_archive {
+                            fetch_archive_input(input, args, show_progress).map(Some)
+                        } else {
+                            fetch_repository(input, args, show_progress)
+                        };
+                        checkout.map_err(|e| {
+                            ExitError::new(exit_code(e.as_ref()), format!("{}: {}", input, e))
+                        })
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("download thread panicked"))
+                .collect()
+        });
+
+        let mut sources = Vec::new();
+        for ((input, _), result) in inputs.iter().zip(results) {
+            if let Some(checkout) = result? {
+                let (root, search_path) = (checkout.root.clone(), checkout.search_path.clone());
+                sources.push(((*input).clone(), root, search_path));
+                checkouts.push(checkout);
             }
-            search_path
-        } else {
-            extracted_path
         }
+        if sources.is_empty() {
+            if args.fail_on_empty {
+                return Err(Box::new(ExitError::new(
+                    EXIT_NO_MATCHES,
+                    "No files matched",
+                )));
+            }
+            return Ok(());
+        }
+        sources
     } else {
-        PathBuf::from(".")
+        for path in &args.paths {
+            if !path.exists() {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Path does not exist: {}", path.display()),
+                )));
+            }
+        }
+        args.paths
+            .iter()
+            .map(|path| (path.display().to_string(), path.clone(), path.clone()))
+            .collect()
     };
 
-    find_files(
-        &search_path,
-        &args.extensions,
-        &args.ignored_dirs,
-        &args.ignored_files, // Pass ignored_files to find_files
-        args.print_contents,
-    )?;
+    for group in &args.types {
+        let extensions = type_extensions(group).ok_or_else(|| {
+            let known: Vec<&str> = TYPE_GROUPS.iter().map(|(name, _)| *name).collect();
+            format!(
+                "Unknown type '{}', expected one of: {}",
+                group,
+                known.join(", ")
+            )
+        })?;
+        args.extensions
+            .extend(extensions.iter().map(|ext| ext.to_string()));
+    }
+    if args.common_config {
+        args.filenames
+            .extend(COMMON_CONFIG_FILES.iter().map(|name| name.to_string()));
+    }
+
+    if let Some(dir) = args
+        .only_dirs
+        .iter()
+        .find(|dir| dir.trim_end_matches('/').contains(['/', '\\']))
+    {
+        return Err(format!(
+            "--only-dirs takes top-level directory names, not paths like '{}'; use --include for folders further down",
+            dir
+        )
+        .into());
+    }
+
+    let ignored_dirs = args.ignored_dirs.clone();
+    let options = SearchOptions {
+        extensions: args.extensions,
+        filenames: args.filenames,
+        case_sensitive: args.case_sensitive,
+        include: args.include,
+        exclude: args.exclude,
+        ignored_dirs: args.ignored_dirs,
+        only_dirs: args.only_dirs,
+        ignored_files: args.ignored_files,
+        respect_gitignore: args.respect_gitignore,
+        follow_symlinks: args.follow_symlinks,
+        stub_ignored: args.stub_ignored,
+        descend_archives: args.descend_archives,
+        max_depth: args.max_depth,
+        follow_imports: args.follow_imports,
+        max_import_depth: args.max_import_depth,
+        since: args.since,
+        max_file_size: args.max_file_size,
+        sort: args.sort,
+        reverse: args.reverse,
+        content_match: args.content_match,
+        content_exclude: args.content_exclude,
+        filter_command: args.filter_command,
+        only_changed: args.only_changed,
+        print_contents: args.print_contents,
+        include_binary: args.include_binary,
+        strict_utf8: args.strict_utf8,
+        tree: args.tree,
+        flatten: args.flatten,
+        with_git_info: args.with_git_info,
+        annotate_headers: args.annotate_headers,
+        overview: args.overview,
+        info_header: args.info_header,
+        // Counting needs only the matches, so nothing is read
+        dry_run: args.dry_run || args.count_only,
+        interactive: args.interactive,
+        dedupe: args.dedupe,
+        exclude_empty: args.exclude_empty,
+        manifest_hash: args.print_manifest_hash,
+        fail_on_read_error: args.fail_on_read_error,
+        transforms: args.transforms,
+        redact_secrets: args.redact_secrets,
+        strip_comments: args.strip_comments,
+        outline: args.outline,
+        normalize_eol: (!args.no_normalize).then_some(args.normalize_eol),
+        line_numbers: args.line_numbers,
+        max_lines_per_file: args.max_lines_per_file,
+        head: args.head,
+        mmap_threshold: args.mmap.then_some(args.mmap_threshold),
+        count_tokens: args.count_tokens || args.model.is_some(),
+        token_cache: if args.no_token_cache {
+            None
+        } else {
+            token_cache_dir()
+        },
+        max_tokens: args.max_tokens,
+        buffer_size: args.buffer_size,
+        format,
+        header_template: args.header_template,
+        footer_template: args.footer_template,
+        progress: show_progress,
+        color,
+    };
+
+    // With --clipboard everything is buffered, then copied (and saved to --output, if given);
+    // with --chunk-tokens it is buffered to be split into parts
+    let mut buffer = Vec::new();
+    let mut out: Box<dyn Write> = match &args.output {
+        _ if args.count_only => Box::new(io::sink()),
+        _ if args.clipboard || args.chunk_tokens.is_some() => Box::new(&mut buffer),
+        Some(path) => {
+            let file = File::create(path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Failed to create output file {}: {}", path.display(), e),
+                )
+            })?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut builder = ContextBuilder::new(options);
+    let mut preamble = match (args.preamble, &args.preamble_file) {
+        (Some(text), _) => Some(text),
+        (None, Some(path)) => Some(fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read preamble file {}: {}", path.display(), e),
+            )
+        })?),
+        (None, None) => None,
+    };
+    // With --base, each repository's diff stat introduces its changed files
+    let diff_stats: Vec<&str> = checkouts
+        .iter()
+        .filter_map(|checkout| checkout.diff_stat.as_deref())
+        .collect();
+    if !diff_stats.is_empty() {
+        let diff_stats = diff_stats.join("\n");
+        preamble = Some(match preamble {
+            Some(text) => format!("{}\n\n{}", text.trim_end(), diff_stats),
+            None => diff_stats,
+        });
+    }
+    if let Some(text) = preamble {
+        builder = builder.preamble(text);
+    }
+    for (format, path) in &args.extra_outputs {
+        builder = builder.output(*format, path);
+    }
+    // By now the output file exists, and it would otherwise end up in its own context
+    if let Some(path) = &args.output {
+        builder = builder.skip_file(path);
+        // Parts left by an earlier --chunk-tokens run
+        for part in (1..)
+            .map(|i| part_path(path, i))
+            .take_while(|part| part.exists())
+        {
+            builder = builder.skip_file(part);
+        }
+    }
+    if let Some(list) = &args.files_from {
+        builder = builder.files(read_file_list(list)?);
+    }
+    if !args.append_file.is_empty() || !args.append_dir.is_empty() {
+        builder =
+            builder.append_files(collect_appended_files(&args.append_file, &args.append_dir)?);
+    }
+    for (label, root, search_path) in sources {
+        builder = builder.labeled_source(label, root, search_path);
+    }
+    if let Some(path) = &args.bundle {
+        builder = builder.bundle(path);
+    }
+    if let Some(dir) = &args.split_output {
+        builder = builder.split_output(dir);
+    }
+    let stats = builder.write_to(&mut out)?;
+    drop(out);
+
+    if args.count_only {
+        println!("{}", stats.files);
+    }
+
+    if let (Some(model), Some(window)) = (&args.model, model_window) {
+        if stats.tokens > window {
+            warn!(
+                "Output is {} tokens but {} holds {}",
+                format_tokens(stats.tokens),
+                model,
+                format_tokens(window)
+            );
+        }
+    }
+
+    if args.clipboard {
+        if let Some(path) = &args.output {
+            fs::write(path, &buffer)
+                .map_err(|e| format!("Failed to write output file {}: {}", path.display(), e))?;
+        }
+        copy_to_clipboard(&buffer)?;
+        if !args.quiet {
+            eprintln!("Copied {} to clipboard", format_size(buffer.len() as u64));
+        }
+    }
+
+    if let (Some(max_tokens), Some(path)) = (args.chunk_tokens, &args.output) {
+        let parts = split_parts(
+            &String::from_utf8_lossy(&buffer),
+            &stats.file_spans,
+            max_tokens,
+        );
+        for (i, part) in parts.iter().enumerate() {
+            let part_file = part_path(path, i + 1);
+            fs::write(&part_file, part).map_err(|e| {
+                format!("Failed to write output file {}: {}", part_file.display(), e)
+            })?;
+        }
+        // Leftovers from an earlier, longer run would look like part of this one
+        for stale in (parts.len() + 1..)
+            .map(|i| part_path(path, i))
+            .take_while(|part| part.exists())
+        {
+            info!("Removing stale {}", stale.display());
+            fs::remove_file(&stale)?;
+        }
+        if !args.quiet {
+            let noun = if parts.len() == 1 { "part" } else { "parts" };
+            eprintln!(
+                "Wrote {} in {} {} to {}",
+                format_size(buffer.len() as u64),
+                parts.len(),
+                noun,
+                part_path(path, 1).display()
+            );
+        }
+    }
+
+    if let Some(path) = args.bundle.as_ref().filter(|_| !args.quiet) {
+        let written = fs::metadata(path)?.len();
+        eprintln!(
+            "Bundled {} files ({}) into {}",
+            stats.bundled,
+            format_size(written),
+            path.display()
+        );
+    }
+
+    if let Some(dir) = args.split_output.as_ref().filter(|_| !args.quiet) {
+        eprintln!("Wrote {} files to {}", stats.split, dir.display());
+    }
+
+    if stats.skipped_empty > 0 && !args.quiet && !args.stats {
+        eprintln!("Skipped {} empty files", stats.skipped_empty);
+    }
+
+    if args.stats {
+        stats.print(&mut io::stderr())?;
+    }
 
+    if let Some(hash) = stats.manifest_hash {
+        eprintln!("Manifest hash: {}", hash);
+    }
+
+    if let Some(path) = args
+        .output
+        .as_ref()
+        .filter(|_| !args.quiet && args.chunk_tokens.is_none())
+    {
+        let written = fs::metadata(path)?.len();
+        eprintln!("Wrote {} to {}", format_size(written), path.display());
+    }
+    for (_, path) in args.extra_outputs.iter().filter(|_| !args.quiet) {
+        let written = fs::metadata(path)?.len();
+        eprintln!("Wrote {} to {}", format_size(written), path.display());
+    }
+
+    if args.fail_on_empty && stats.files == 0 {
+        return Err(Box::new(ExitError::new(
+            EXIT_NO_MATCHES,
+            "No files matched",
+        )));
+    }
+
+    if args.watch {
+        let output = args.output.as_deref().expect("--watch requires --output");
+        // Writing the output must not count as a change, or every rebuild would trigger another
+        let written = [
+            Some(output),
+            args.bundle.as_deref(),
+            args.split_output.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+        let watch = Watch {
+            paths: &args.paths,
+            output,
+            written,
+            ignored_dirs,
+            quiet: args.quiet,
+        };
+        watch.run(&builder)?;
+    }
     Ok(())
 }
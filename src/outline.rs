@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use tree_sitter::{Language, Node, Parser};
+
+use crate::comments::strip_comments;
+
+/// Reduces `contents`, written in `language` as named by
+/// [`detect_language`](crate::detect_language), to its declarations for `--outline`.
+///
+/// The file is parsed with its tree-sitter grammar. Function, method, and closure bodies
+/// become `{ ... }` (or `...` in Python) while everything else is kept, so the members of
+/// types, traits, impls, classes, modules, and namespaces are listed too. Comments are removed
+/// first, and runs of blank lines left behind collapse into one. Languages without a grammar
+/// are returned unchanged.
+pub fn outline(contents: &str, language: &str) -> String {
+    let Some(grammar) = Grammar::for_language(language) else {
+        return contents.to_string();
+    };
+    let stripped = strip_comments(contents, language);
+    let mut parser = Parser::new();
+    if parser.set_language(&grammar.language).is_err() {
+        return contents.to_string();
+    }
+    let Some(tree) = parser.parse(&stripped, None) else {
+        return contents.to_string();
+    };
+
+    let mut output = String::with_capacity(stripped.len());
+    let mut pos = 0;
+    for body in function_bodies(tree.root_node(), &grammar) {
+        output.push_str(&stripped[pos..body.start]);
+        output.push_str(grammar.elided);
+        pos = body.end;
+    }
+    output.push_str(&stripped[pos..]);
+    collapse_blank_lines(&output)
+}
+
+/// What to elide in one language's syntax tree.
+struct Grammar {
+    language: Language,
+    /// Nodes whose `body` field is elided
+    functions: &'static [&'static str],
+    /// Kinds of `body` that are elided; a closure returning an expression is kept whole
+    blocks: &'static [&'static str],
+    /// Printed in place of an elided body
+    elided: &'static str,
+}
+
+const JS_FUNCTIONS: &[&str] = &[
+    "function_declaration",
+    "generator_function_declaration",
+    "function_expression",
+    "generator_function",
+    "arrow_function",
+    "method_definition",
+];
+
+impl Grammar {
+    fn for_language(language: &str) -> Option<Grammar> {
+        let braces = |language: Language, functions, blocks| Grammar {
+            language,
+            functions,
+            blocks,
+            elided: "{ ... }",
+        };
+        let grammar = match language {
+            "rust" => braces(
+                tree_sitter_rust::LANGUAGE.into(),
+                &["function_item", "closure_expression"],
+                &["block"],
+            ),
+            "python" => Grammar {
+                language: tree_sitter_python::LANGUAGE.into(),
+                functions: &["function_definition"],
+                blocks: &["block"],
+                elided: "...",
+            },
+            "javascript" | "jsx" => braces(
+                tree_sitter_javascript::LANGUAGE.into(),
+                JS_FUNCTIONS,
+                &["statement_block"],
+            ),
+            "typescript" => braces(
+                tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+                JS_FUNCTIONS,
+                &["statement_block"],
+            ),
+            "tsx" => braces(
+                tree_sitter_typescript::LANGUAGE_TSX.into(),
+                JS_FUNCTIONS,
+                &["statement_block"],
+            ),
+            "go" => braces(
+                tree_sitter_go::LANGUAGE.into(),
+                &["function_declaration", "method_declaration", "func_literal"],
+                &["block"],
+            ),
+            "java" => braces(
+                tree_sitter_java::LANGUAGE.into(),
+                &[
+                    "method_declaration",
+                    "constructor_declaration",
+                    "compact_constructor_declaration",
+                    "lambda_expression",
+                ],
+                &["block", "constructor_body"],
+            ),
+            "c" => braces(
+                tree_sitter_c::LANGUAGE.into(),
+                &["function_definition"],
+                &["compound_statement"],
+            ),
+            "cpp" => braces(
+                tree_sitter_cpp::LANGUAGE.into(),
+                &["function_definition", "lambda_expression"],
+                &["compound_statement", "try_statement"],
+            ),
+            _ => return None,
+        };
+        Some(grammar)
+    }
+}
+
+/// Byte ranges of the outermost function bodies under `root`, in order. Nothing inside an
+/// elided body is visited, so a class declared in a function disappears with it.
+fn function_bodies(root: Node, grammar: &Grammar) -> Vec<Range<usize>> {
+    let mut bodies = Vec::new();
+    // Walked without recursion, since deeply nested expressions are common in generated code
+    let mut pending = vec![root];
+    let mut cursor = root.walk();
+    while let Some(node) = pending.pop() {
+        if grammar.functions.contains(&node.kind()) {
+            if let Some(body) = node.child_by_field_name("body") {
+                if grammar.blocks.contains(&body.kind()) {
+                    bodies.push(body.byte_range());
+                    continue;
+                }
+            }
+        }
+        pending.extend(node.children(&mut cursor));
+    }
+    bodies.sort_by_key(|body| body.start);
+    bodies
+}
+
+fn collapse_blank_lines(contents: &str) -> String {
+    let mut output = String::with_capacity(contents.len());
+    let mut blank = false;
+    for line in contents.lines() {
+        let is_blank = line.trim().is_empty();
+        if is_blank && blank {
+            continue;
+        }
+        blank = is_blank;
+        output.push_str(if is_blank { "" } else { line.trim_end() });
+        output.push('\n');
+    }
+    output
+}
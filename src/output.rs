@@ -0,0 +1,1308 @@
+use crate::error::ContextError;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info, warn};
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::comments::strip_comments;
+use crate::content::{
+    add_line_numbers, cached_token_count, count_tokens, read_contents, redact, sniff_binary, to_lf,
+    truncate_lines, LineEnding,
+};
+use crate::git::CommitInfo;
+use crate::language::detect_language;
+use crate::outline::outline;
+use crate::overview::Overview;
+use crate::search::{log_skip, MatchedFile, SearchOptions, SkipReason};
+use crate::transform::apply_transform;
+use crate::util::format_size;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// `# File:` headers followed by raw contents
+    #[default]
+    Plain,
+    /// Markdown headings with fenced code blocks
+    Markdown,
+    /// `<file>` elements wrapped in a `<documents>` root
+    Xml,
+    /// A single JSON array of `{ path, size, contents }` objects
+    Json,
+    /// One `path<TAB>bytes<TAB>language` line per file, without contents
+    Manifest,
+}
+
+/// Escapes markup in `s` for XML text. Characters XML 1.0 doesn't allow at all, such as the
+/// escape in ANSI-colored logs or a form feed, become U+FFFD.
+pub(crate) fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\0'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => escaped.push(char::REPLACEMENT_CHARACTER),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn escape_xml_attr(s: &str) -> String {
+    escape_xml(s).replace('"', "&quot;")
+}
+
+/// One element of the `--format json` array.
+#[derive(Serialize)]
+pub(crate) struct JsonFile<'a> {
+    /// The searched path or repository the file belongs to, when there are several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) root: Option<&'a str>,
+    pub(crate) path: String,
+    pub(crate) size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) contents: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) duplicate_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) commit: Option<&'a CommitInfo>,
+}
+
+/// Substitutes `{path}` in a `--header-template` or `--footer-template`.
+pub(crate) fn render_template(tmpl: &str, path: &str) -> String {
+    tmpl.replace("{path}", path)
+}
+
+/// Highlights a plain-format file header when writing to a terminal.
+pub(crate) fn paint_header(line: String, options: &SearchOptions) -> String {
+    if options.color {
+        line.bold().cyan().to_string()
+    } else {
+        line
+    }
+}
+
+pub(crate) fn paint_separator(line: String, options: &SearchOptions) -> String {
+    if options.color {
+        line.dimmed().to_string()
+    } else {
+        line
+    }
+}
+
+pub(crate) fn print_plain_footer(
+    out: &mut dyn Write,
+    path: &Path,
+    options: &SearchOptions,
+) -> io::Result<()> {
+    let footer = match &options.footer_template {
+        Some(tmpl) => render_template(tmpl, &path.display().to_string()),
+        None => format!("# {}", "-".repeat(50)),
+    };
+    writeln!(out, "{}", paint_separator(footer, options))
+}
+
+pub(crate) fn print_file(
+    out: &mut dyn Write,
+    file: &MatchedFile,
+    contents: &str,
+    tokens: Option<usize>,
+    options: &SearchOptions,
+    group: Option<&str>,
+) -> io::Result<()> {
+    let path = file.display_path.as_path();
+    let mut title = path.display().to_string();
+    if let Some(commit) = &file.commit {
+        title = format!(
+            "{} ({}, {}, {})",
+            title, commit.hash, commit.author, commit.date
+        );
+    }
+    // `--annotate-headers` puts the size in the same parentheses as any token count
+    let details = match (options.annotate_headers, tokens) {
+        (true, Some(tokens)) => Some(format!("{}, {} tokens", format_size(file.size), tokens)),
+        (true, None) => Some(format_size(file.size)),
+        (false, Some(tokens)) => Some(format!("{} tokens", tokens)),
+        (false, None) => None,
+    };
+    if let Some(details) = details {
+        title = format!("{} ({})", title, details);
+    }
+
+    match options.format {
+        OutputFormat::Plain => {
+            let header = match &options.header_template {
+                Some(tmpl) => render_template(tmpl, &path.display().to_string()),
+                None => format!("# File: {}", title),
+            };
+            writeln!(out, "{}", paint_header(header, options))?;
+            writeln!(out, "{}", contents)?;
+            print_plain_footer(out, path, options)?;
+        }
+        OutputFormat::Markdown => {
+            let language = file
+                .language
+                .clone()
+                .or_else(|| detect_language(path, contents.lines().next()))
+                .unwrap_or_default();
+            // The fence must be longer than any backtick run inside the file
+            let longest_run = contents
+                .split(|c| c != '`')
+                .map(str::len)
+                .max()
+                .unwrap_or(0);
+            let fence = "`".repeat(longest_run.max(2) + 1);
+            writeln!(out, "## {}", title)?;
+            writeln!(out)?;
+            writeln!(out, "{}{}", fence, language)?;
+            writeln!(out, "{}", contents.trim_end_matches('\n'))?;
+            writeln!(out, "{}", fence)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Xml => {
+            let mut attrs = format!("path=\"{}\"", escape_xml_attr(&path.display().to_string()));
+            if let Some(commit) = &file.commit {
+                attrs += &format!(
+                    " commit=\"{}\" author=\"{}\" date=\"{}\"",
+                    escape_xml_attr(&commit.hash),
+                    escape_xml_attr(&commit.author),
+                    escape_xml_attr(&commit.date)
+                );
+            }
+            if options.annotate_headers {
+                attrs += &format!(" size=\"{}\"", file.size);
+            }
+            if let Some(tokens) = tokens {
+                attrs += &format!(" tokens=\"{}\"", tokens);
+            }
+            writeln!(out, "<file {}>", attrs)?;
+            writeln!(out, "{}", escape_xml(contents.trim_end_matches('\n')))?;
+            writeln!(out, "</file>")?;
+        }
+        OutputFormat::Json => {
+            let entry = JsonFile {
+                root: group,
+                path: path.display().to_string(),
+                size: file.size,
+                tokens,
+                contents: Some(contents),
+                duplicate_of: None,
+                commit: file.commit.as_ref(),
+            };
+            serde_json::to_writer_pretty(&mut *out, &entry)?;
+        }
+        OutputFormat::Manifest => print_manifest_line(out, file)?,
+    }
+    Ok(())
+}
+
+/// Manifests are built from metadata, so only the file name, extension, and any
+/// `.gitattributes` override decide the language.
+pub(crate) fn print_manifest_line(out: &mut dyn Write, file: &MatchedFile) -> io::Result<()> {
+    let language = file
+        .language
+        .clone()
+        .or_else(|| detect_language(&file.display_path, None))
+        .unwrap_or_else(|| "text".to_string());
+    writeln!(
+        out,
+        "{}\t{}\t{}",
+        file.display_path.display(),
+        file.size,
+        language
+    )
+}
+
+/// Writes a reference to `original` in place of a file whose contents are identical to it.
+pub(crate) fn print_duplicate(
+    out: &mut dyn Write,
+    file: &RenderedFile,
+    original: &Path,
+    options: &SearchOptions,
+    group: Option<&str>,
+) -> io::Result<()> {
+    let path = file.display_path.as_path();
+    match options.format {
+        OutputFormat::Plain => {
+            let reference = format!("{} (duplicate of {})", path.display(), original.display());
+            let header = match &options.header_template {
+                Some(tmpl) => render_template(tmpl, &reference),
+                None => format!("# File: {}", reference),
+            };
+            writeln!(out, "{}", paint_header(header, options))?;
+            print_plain_footer(out, path, options)
+        }
+        OutputFormat::Markdown => {
+            writeln!(
+                out,
+                "## {} (duplicate of {})",
+                path.display(),
+                original.display()
+            )?;
+            writeln!(out)
+        }
+        OutputFormat::Xml => writeln!(
+            out,
+            "<file path=\"{}\" duplicate-of=\"{}\"/>",
+            escape_xml_attr(&path.display().to_string()),
+            escape_xml_attr(&original.display().to_string())
+        ),
+        OutputFormat::Json => {
+            let entry = JsonFile {
+                root: group,
+                path: path.display().to_string(),
+                size: file.size,
+                tokens: None,
+                contents: None,
+                duplicate_of: Some(original.display().to_string()),
+                commit: None,
+            };
+            serde_json::to_writer_pretty(&mut *out, &entry).map_err(io::Error::from)
+        }
+        OutputFormat::Manifest => writeln!(
+            out,
+            "{}\t{}\t{}",
+            path.display(),
+            file.size,
+            detect_language(path, None).unwrap_or_else(|| "text".to_string())
+        ),
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TreeNode {
+    pub(crate) children: BTreeMap<String, TreeNode>,
+}
+
+/// Renders `paths` as an ASCII directory tree in the style of the `tree` command.
+pub(crate) fn render_tree(paths: &[PathBuf], root: &Path) -> String {
+    let mut tree = TreeNode::default();
+    for path in paths {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let mut node = &mut tree;
+        for component in relative.components() {
+            if let Component::Normal(name) = component {
+                node = node
+                    .children
+                    .entry(name.to_string_lossy().into_owned())
+                    .or_default();
+            }
+        }
+    }
+
+    let mut output = String::from(".\n");
+    render_tree_node(&tree, "", &mut output);
+    output
+}
+
+pub(crate) fn render_tree_node(node: &TreeNode, prefix: &str, output: &mut String) {
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let is_last = i + 1 == count;
+        output.push_str(prefix);
+        output.push_str(if is_last { "└── " } else { "├── " });
+        output.push_str(name);
+        output.push('\n');
+
+        let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+        render_tree_node(child, &child_prefix, output);
+    }
+}
+
+pub(crate) fn print_tree(out: &mut dyn Write, tree: &str, format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Plain => {
+            writeln!(out, "# Tree")?;
+            write!(out, "{}", tree)?;
+            writeln!(out, "# {}", "-".repeat(50))?;
+        }
+        OutputFormat::Markdown => {
+            writeln!(out, "## Tree")?;
+            writeln!(out)?;
+            writeln!(out, "```text")?;
+            write!(out, "{}", tree)?;
+            writeln!(out, "```")?;
+            writeln!(out)?;
+        }
+        OutputFormat::Xml => {
+            writeln!(out, "<tree>")?;
+            write!(out, "{}", escape_xml(tree))?;
+            writeln!(out, "</tree>")?;
+        }
+        // These stay machine readable; paths are already listed per entry
+        OutputFormat::Json | OutputFormat::Manifest => {}
+    }
+    Ok(())
+}
+
+/// One line per directory left out by `--ignored-dirs`, so the model still knows it exists.
+pub(crate) fn print_ignored_stubs(
+    out: &mut dyn Write,
+    ignored: &[(PathBuf, usize)],
+    format: OutputFormat,
+) -> io::Result<()> {
+    let describe = |path: &Path, files: usize| {
+        let noun = if files == 1 { "file" } else { "files" };
+        format!("{}/ — {} {}", path.display(), files, noun)
+    };
+    match format {
+        OutputFormat::Plain => {
+            for (path, files) in ignored {
+                writeln!(out, "# (excluded directory: {})", describe(path, *files))?;
+            }
+            writeln!(out)?;
+        }
+        OutputFormat::Markdown => {
+            for (path, files) in ignored {
+                writeln!(out, "_(excluded directory: {})_", describe(path, *files))?;
+                writeln!(out)?;
+            }
+        }
+        OutputFormat::Xml => {
+            for (path, files) in ignored {
+                writeln!(
+                    out,
+                    "<excluded path=\"{}/\" files=\"{}\"/>",
+                    escape_xml_attr(&path.display().to_string()),
+                    files
+                )?;
+            }
+        }
+        OutputFormat::Json | OutputFormat::Manifest => {}
+    }
+    Ok(())
+}
+
+pub(crate) fn print_overview(
+    out: &mut dyn Write,
+    overview: &str,
+    format: OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Plain => {
+            writeln!(out, "# Overview")?;
+            write!(out, "{}", overview)?;
+            writeln!(out, "# {}", "-".repeat(50))?;
+        }
+        OutputFormat::Markdown => {
+            writeln!(out, "## Overview")?;
+            writeln!(out)?;
+            writeln!(out, "```text")?;
+            write!(out, "{}", overview)?;
+            writeln!(out, "```")?;
+            writeln!(out)?;
+        }
+        OutputFormat::Xml => {
+            writeln!(out, "<overview>")?;
+            write!(out, "{}", escape_xml(overview))?;
+            writeln!(out, "</overview>")?;
+        }
+        OutputFormat::Json | OutputFormat::Manifest => {}
+    }
+    Ok(())
+}
+
+/// The `--info-header` summary of the files about to be printed.
+#[derive(Serialize)]
+pub(crate) struct InfoHeader {
+    pub(crate) files: usize,
+    pub(crate) bytes: u64,
+    pub(crate) estimated_tokens: u64,
+    pub(crate) entries: Vec<InfoEntry>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct InfoEntry {
+    pub(crate) path: String,
+    pub(crate) size: u64,
+    pub(crate) estimated_tokens: u64,
+}
+
+/// Rough token count from the size alone, about four bytes per token, so the header can be
+/// written before any file is read.
+pub(crate) fn estimate_tokens(size: u64) -> u64 {
+    size.div_ceil(4)
+}
+
+impl InfoHeader {
+    pub(crate) fn collect(matched: &[MatchedFile]) -> Self {
+        let entries: Vec<InfoEntry> = matched
+            .iter()
+            .map(|file| InfoEntry {
+                path: file.display_path.display().to_string(),
+                size: file.size,
+                estimated_tokens: estimate_tokens(file.size),
+            })
+            .collect();
+        InfoHeader {
+            files: entries.len(),
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+            estimated_tokens: entries.iter().map(|entry| entry.estimated_tokens).sum(),
+            entries,
+        }
+    }
+}
+
+/// Writes the `--info-header` block as fenced single-line JSON. Only the plain and Markdown
+/// formats get one; the structured formats already describe their files.
+pub(crate) fn print_info_header(
+    out: &mut dyn Write,
+    header: &InfoHeader,
+    format: OutputFormat,
+) -> Result<(), ContextError> {
+    if !matches!(format, OutputFormat::Plain | OutputFormat::Markdown) {
+        return Ok(());
+    }
+    writeln!(out, "```json")?;
+    serde_json::to_writer(&mut *out, header).map_err(io::Error::from)?;
+    writeln!(out)?;
+    writeln!(out, "```")?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// A matched file whose contents have been read and rendered in the output format.
+pub(crate) struct RenderedFile {
+    pub(crate) display_path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) tokens: Option<usize>,
+    /// Hash of the file contents, computed for `--dedupe`
+    pub(crate) hash: Option<blake3::Hash>,
+    /// Tokens removed by `--strip-comments`, when tokens are counted
+    pub(crate) tokens_saved: usize,
+    pub(crate) rendered: Vec<u8>,
+}
+
+/// Applies `--normalize-eol`, `--redact-secrets`, `--line-numbers`, and
+/// `--max-lines-per-file` to the text of the file shown as `display_path`.
+pub(crate) fn transform_contents(
+    mut contents: String,
+    display_path: &Path,
+    options: &SearchOptions,
+) -> String {
+    // Everything below splits on `\n`, so CRLF is only put back at the end
+    if options.normalize_eol.is_some() {
+        if let Cow::Owned(normalized) = to_lf(&contents) {
+            contents = normalized;
+        }
+    }
+    if options.redact_secrets {
+        let (redacted, count) = redact(&contents);
+        if count > 0 {
+            warn!("Redacted {} secret(s) in {}", count, display_path.display());
+        }
+        contents = redacted;
+    }
+    if options.line_numbers {
+        contents = add_line_numbers(&contents);
+    }
+    // Truncating after numbering keeps the original line numbers on both halves
+    if let Some(truncated) = options
+        .max_lines_per_file
+        .and_then(|max_lines| truncate_lines(&contents, max_lines))
+    {
+        info!("Truncated {}", display_path.display());
+        contents = truncated;
+    }
+    if options.normalize_eol == Some(LineEnding::Crlf) {
+        contents = contents.replace('\n', "\r\n");
+    }
+    contents
+}
+
+/// The file's language: `language`, its `linguist-language` override from `.gitattributes`,
+/// when set, else detected from its path and first line.
+fn file_language(contents: &str, display_path: &Path, language: Option<&str>) -> Option<String> {
+    language
+        .map(str::to_string)
+        .or_else(|| detect_language(display_path, contents.lines().next()))
+}
+
+/// Strips comments for `--strip-comments` in the syntax of the file's language, if known.
+pub(crate) fn strip_file_comments(
+    contents: String,
+    display_path: &Path,
+    language: Option<&str>,
+) -> String {
+    match file_language(&contents, display_path, language) {
+        Some(language) => strip_comments(&contents, &language),
+        None => contents,
+    }
+}
+
+/// Reduces the file to its declarations for `--outline` if its language has an outline.
+pub(crate) fn outline_file(
+    contents: String,
+    display_path: &Path,
+    language: Option<&str>,
+) -> String {
+    match file_language(&contents, display_path, language) {
+        Some(language) => outline(&contents, &language),
+        None => contents,
+    }
+}
+
+/// Counts the tokens of a printed file, through the token cache when one is configured.
+fn file_tokens(contents: &str, options: &SearchOptions) -> usize {
+    match &options.token_cache {
+        Some(cache_dir) => cached_token_count(cache_dir, contents),
+        None => count_tokens(contents),
+    }
+}
+
+/// The text of a matched file as it is printed or split out.
+pub(crate) struct ProcessedFile {
+    pub(crate) contents: String,
+    /// Hash of the file contents before any processing, computed for `--dedupe`
+    pub(crate) hash: Option<blake3::Hash>,
+    /// Tokens removed by `--strip-comments`, when tokens are counted
+    pub(crate) tokens_saved: usize,
+}
+
+/// Reads a matched file and runs it through the `transforms`, `--strip-comments`,
+/// `--outline`, and [`transform_contents`]. Skipped binary files give `Ok(None)`.
+pub(crate) fn process_file(
+    file: &MatchedFile,
+    options: &SearchOptions,
+) -> io::Result<Option<ProcessedFile>> {
+    let Some(mut contents) = read_contents(file, options)? else {
+        return Ok(None);
+    };
+    let hash = options.dedupe.then(|| blake3::hash(contents.as_bytes()));
+    let extension = file.display_path.extension();
+    if let Some(ext) = extension.filter(|_| !options.transforms.is_empty()) {
+        match apply_transform(&ext.to_string_lossy(), &contents, &options.transforms) {
+            Ok(transformed) => contents = transformed,
+            Err(e) => warn!("Not transforming {}: {}", file.display_path.display(), e),
+        }
+    }
+    let needs_tokens = options.count_tokens || options.max_tokens.is_some();
+    let mut tokens_saved = 0;
+    if options.strip_comments {
+        let stripped = strip_file_comments(
+            contents.clone(),
+            &file.display_path,
+            file.language.as_deref(),
+        );
+        if needs_tokens {
+            tokens_saved =
+                file_tokens(&contents, options).saturating_sub(file_tokens(&stripped, options));
+        }
+        contents = stripped;
+    }
+    if options.outline {
+        contents = outline_file(contents, &file.display_path, file.language.as_deref());
+    }
+    Ok(Some(ProcessedFile {
+        contents: transform_contents(contents, &file.display_path, options),
+        hash,
+        tokens_saved,
+    }))
+}
+
+pub(crate) fn render_file(
+    file: &MatchedFile,
+    options: &SearchOptions,
+    group: Option<&str>,
+) -> io::Result<Option<RenderedFile>> {
+    let Some(ProcessedFile {
+        contents,
+        hash,
+        tokens_saved,
+    }) = process_file(file, options)?
+    else {
+        return Ok(None);
+    };
+    let needs_tokens = options.count_tokens || options.max_tokens.is_some();
+    let tokens = needs_tokens.then(|| file_tokens(&contents, options));
+
+    let mut rendered = Vec::new();
+    print_file(&mut rendered, file, &contents, tokens, options, group)?;
+    Ok(Some(RenderedFile {
+        display_path: file.display_path.clone(),
+        size: file.size,
+        tokens,
+        hash,
+        tokens_saved,
+        rendered,
+    }))
+}
+
+/// Writes the `--preamble` text ahead of everything else. In JSON the file array becomes the
+/// `files` field of an object next to it; [`print_preamble_end`] closes that object.
+pub fn print_preamble(out: &mut dyn Write, preamble: &str, format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Plain | OutputFormat::Markdown | OutputFormat::Manifest => {
+            writeln!(out, "{}", preamble.trim_end())?;
+            writeln!(out)
+        }
+        OutputFormat::Xml => writeln!(
+            out,
+            "<preamble>{}</preamble>",
+            escape_xml(preamble.trim_end())
+        ),
+        OutputFormat::Json => {
+            writeln!(out, "{{")?;
+            writeln!(out, "\"preamble\": {},", serde_json::to_string(preamble)?)?;
+            write!(out, "\"files\": ")
+        }
+    }
+}
+
+pub fn print_preamble_end(out: &mut dyn Write, format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => writeln!(out, "}}"),
+        OutputFormat::Plain
+        | OutputFormat::Markdown
+        | OutputFormat::Xml
+        | OutputFormat::Manifest => Ok(()),
+    }
+}
+
+pub(crate) fn print_group_header(
+    out: &mut dyn Write,
+    group: &str,
+    format: OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Plain | OutputFormat::Xml | OutputFormat::Manifest => {
+            writeln!(out, "# Root: {}", group)
+        }
+        OutputFormat::Markdown => {
+            writeln!(out, "# {}", group)?;
+            writeln!(out)
+        }
+        OutputFormat::Json => Ok(()),
+    }
+}
+
+/// Separates the files from `--append-file` and `--append-dir` from the matched files.
+pub(crate) fn print_appendix_header(out: &mut dyn Write, format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Plain | OutputFormat::Manifest => writeln!(out, "# Appended files"),
+        OutputFormat::Markdown => {
+            writeln!(out, "# Appended files")?;
+            writeln!(out)
+        }
+        OutputFormat::Xml => writeln!(out, "<!-- Appended files -->"),
+        OutputFormat::Json => Ok(()),
+    }
+}
+
+/// Replaces each display path with its bare file name for `--flatten`. Later files whose
+/// name is already taken get a numeric suffix before the extension, e.g. `mod-2.rs`.
+pub(crate) fn flatten_paths(matched: &[MatchedFile]) -> Vec<MatchedFile> {
+    let mut taken = HashSet::new();
+    matched
+        .iter()
+        .map(|file| {
+            let path = &file.display_path;
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut flat_name = name.clone();
+            let mut count = 1;
+            while taken.contains(&flat_name) {
+                count += 1;
+                flat_name = match (path.file_stem(), path.extension()) {
+                    (Some(stem), Some(ext)) => format!(
+                        "{}-{}.{}",
+                        stem.to_string_lossy(),
+                        count,
+                        ext.to_string_lossy()
+                    ),
+                    _ => format!("{}-{}", name, count),
+                };
+            }
+            taken.insert(flat_name.clone());
+            MatchedFile {
+                display_path: PathBuf::from(flat_name),
+                ..file.clone()
+            }
+        })
+        .collect()
+}
+
+/// Files rendered ahead of the writer when `--buffer-size` isn't given.
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 64;
+
+/// Renders `matched` on worker threads and hands each result to `write` in path order.
+///
+/// A worker only starts file `i` once fewer than `window` files before it are still waiting
+/// to be written, so a slow file stalls the workers instead of letting rendered output pile
+/// up in memory; at most `window` rendered files are held at once.
+pub(crate) fn render_in_order(
+    matched: &[MatchedFile],
+    options: &SearchOptions,
+    group: Option<&str>,
+    window: usize,
+    progress: &ProgressBar,
+    mut write: impl FnMut(RenderedFile) -> Result<(), ContextError>,
+) -> Result<Vec<PathBuf>, ContextError> {
+    let window = window.max(1);
+    let next_index = AtomicUsize::new(0);
+    // How many files the writer has taken, and whether it has stopped early
+    let written = Mutex::new((0, false));
+    let advanced = Condvar::new();
+    let (sender, receiver) = mpsc::sync_channel(window);
+
+    thread::scope(|scope| {
+        for _ in 0..rayon::current_num_threads().min(matched.len()) {
+            let sender = sender.clone();
+            let (next_index, written, advanced) = (&next_index, &written, &advanced);
+            scope.spawn(move || loop {
+                let i = next_index.fetch_add(1, Ordering::Relaxed);
+                if i >= matched.len() {
+                    break;
+                }
+                let state = advanced
+                    .wait_while(written.lock().unwrap(), |(count, stopped)| {
+                        !*stopped && i >= *count + window
+                    })
+                    .unwrap();
+                if state.1 {
+                    break;
+                }
+                drop(state);
+
+                let rendered = render_file(&matched[i], options, group);
+                progress.inc(1);
+                if sender.send((i, rendered)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut write_all = || {
+            // Results that arrived ahead of the next file to write, keyed by index
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            let mut read_errors = Vec::new();
+            for (i, rendered) in &receiver {
+                pending.insert(i, rendered);
+                while let Some(rendered) = pending.remove(&next) {
+                    next += 1;
+                    written.lock().unwrap().0 = next;
+                    advanced.notify_all();
+                    match rendered {
+                        Ok(Some(rendered)) => write(rendered)?,
+                        Ok(None) => {}
+                        Err(_) => read_errors.push(matched[next - 1].display_path.clone()),
+                    }
+                }
+            }
+            Ok(read_errors)
+        };
+        let result = write_all();
+        // Release workers still waiting for room so the scope can end after a write error
+        written.lock().unwrap().1 = true;
+        advanced.notify_all();
+        drop(receiver);
+        result
+    })
+}
+
+/// Writes `matched` as a complete document in the selected format and returns the number of
+/// tokens printed, if they were counted.
+pub fn print_files(
+    matched: &[MatchedFile],
+    root: &Path,
+    options: &SearchOptions,
+    out: &mut dyn Write,
+    group: Option<&str>,
+) -> Result<usize, ContextError> {
+    let mut document = Document::start(out, options, None)?;
+    let printed = write_files(matched, root, options, &mut document, group, &[])?;
+    document.finish()?;
+    Ok(printed.tokens)
+}
+
+/// What [`write_files`] printed.
+#[derive(Debug, Default)]
+pub(crate) struct Printed {
+    /// Tokens printed, when they were counted
+    pub(crate) tokens: usize,
+    /// Display paths of files whose contents could not be read
+    pub(crate) read_errors: Vec<PathBuf>,
+    /// Byte range of each printed file in the document. The first also covers the headers
+    /// before it, so nothing is separated from the file it introduces.
+    pub(crate) spans: Vec<Range<usize>>,
+}
+
+/// A document written in one or more passes, such as the `--files-from` list, each searched
+/// path, and the appended files, counting the bytes that pass through to `out`.
+///
+/// An XML document gets a single `<documents>` root around every pass, and a JSON document
+/// a single array of files, opened by [`Document::start`] and closed by [`Document::finish`];
+/// a dry run lists paths instead.
+pub(crate) struct Document<'a> {
+    out: &'a mut dyn Write,
+    written: usize,
+    format: OutputFormat,
+    wrap_xml: bool,
+    json_array: bool,
+    /// Whether an element of the JSON array has been written, so the next needs a comma
+    json_element: bool,
+    preamble: bool,
+}
+
+impl<'a> Document<'a> {
+    /// Starts the document on `out`, beginning with the `preamble` when there is one.
+    pub(crate) fn start(
+        out: &'a mut dyn Write,
+        options: &SearchOptions,
+        preamble: Option<&str>,
+    ) -> io::Result<Self> {
+        let mut document = Document {
+            out,
+            written: 0,
+            format: options.format,
+            wrap_xml: options.format == OutputFormat::Xml && !options.dry_run,
+            json_array: options.format == OutputFormat::Json && !options.dry_run,
+            json_element: false,
+            preamble: preamble.is_some(),
+        };
+        if document.wrap_xml {
+            writeln!(document, "<documents>")?;
+        }
+        if let Some(preamble) = preamble {
+            print_preamble(&mut document, preamble, options.format)?;
+        }
+        if document.json_array {
+            writeln!(document, "[")?;
+        }
+        Ok(document)
+    }
+
+    /// Separates the next element of the JSON array from the one before it, if any.
+    pub(crate) fn start_element(&mut self) -> io::Result<()> {
+        if self.json_element {
+            writeln!(self, ",")?;
+        }
+        self.json_element = true;
+        Ok(())
+    }
+
+    /// Bytes written so far.
+    pub(crate) fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Closes what [`Document::start`] opened and flushes the output.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        if self.json_array {
+            writeln!(self, "\n]")?;
+        }
+        if self.preamble {
+            let format = self.format;
+            print_preamble_end(&mut self, format)?;
+        }
+        if self.wrap_xml {
+            writeln!(self, "</documents>")?;
+        }
+        self.flush()
+    }
+}
+
+impl Write for Document<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Adds `matched` to `out` like [`print_files`], also reporting the files that could not be
+/// read. `ignored` holds the `--stub-ignored` directories and their file counts. In XML a
+/// `group` becomes a `<root path="...">` element around the files.
+pub(crate) fn write_files(
+    matched: &[MatchedFile],
+    root: &Path,
+    options: &SearchOptions,
+    out: &mut Document,
+    group: Option<&str>,
+    ignored: &[(PathBuf, usize)],
+) -> Result<Printed, ContextError> {
+    let call_start = out.written();
+    let flattened;
+    let matched = if options.flatten {
+        flattened = flatten_paths(matched);
+        &flattened[..]
+    } else {
+        matched
+    };
+    let wrap_xml = options.format == OutputFormat::Xml && !options.dry_run;
+    if let Some(group) = group.filter(|_| !wrap_xml) {
+        print_group_header(out, group, options.format)?;
+    }
+
+    if options.dry_run {
+        for file in matched {
+            writeln!(
+                out,
+                "{} ({})",
+                file.display_path.display(),
+                format_size(file.size)
+            )?;
+        }
+        let total_size: u64 = matched.iter().map(|file| file.size).sum();
+        writeln!(out, "{} files, {}", matched.len(), format_size(total_size))?;
+        out.flush()?;
+        return Ok(Printed::default());
+    }
+
+    // A manifest is built from metadata alone, so contents are never read
+    if options.format == OutputFormat::Manifest {
+        for file in matched {
+            print_manifest_line(out, file)?;
+        }
+        out.flush()?;
+        return Ok(Printed::default());
+    }
+
+    let json = options.format == OutputFormat::Json;
+    if !options.print_contents
+        && !options.tree
+        && !options.overview
+        && !options.info_header
+        && !json
+    {
+        return Ok(Printed::default());
+    }
+
+    let xml_root = group.filter(|_| wrap_xml);
+    if let Some(group) = xml_root {
+        writeln!(out, "<root path=\"{}\">", escape_xml_attr(group))?;
+    }
+
+    if options.info_header {
+        print_info_header(out, &InfoHeader::collect(matched), options.format)?;
+    }
+
+    if options.overview {
+        let overview = Overview::collect(matched, root);
+        print_overview(out, &overview.render(), options.format)?;
+    }
+
+    if options.tree {
+        // Display paths, so a flattened listing doesn't reveal the directories after all
+        let paths: Vec<PathBuf> = matched
+            .iter()
+            .map(|file| file.display_path.clone())
+            .collect();
+        print_tree(out, &render_tree(&paths, Path::new("")), options.format)?;
+    }
+
+    if !ignored.is_empty() {
+        print_ignored_stubs(out, ignored, options.format)?;
+    }
+
+    if !options.print_contents {
+        if json {
+            for file in matched {
+                out.start_element()?;
+                let entry = JsonFile {
+                    root: group,
+                    path: file.display_path.display().to_string(),
+                    size: file.size,
+                    tokens: None,
+                    contents: None,
+                    duplicate_of: None,
+                    commit: file.commit.as_ref(),
+                };
+                serde_json::to_writer_pretty(&mut *out, &entry).map_err(io::Error::from)?;
+            }
+        }
+        if xml_root.is_some() {
+            writeln!(out, "</root>")?;
+        }
+        out.flush()?;
+        return Ok(Printed::default());
+    }
+
+    let progress = if options.progress {
+        ProgressBar::new(matched.len() as u64).with_style(
+            ProgressStyle::with_template("Reading [{bar:40}] {pos}/{len} files")
+                .expect("progress template is valid")
+                .progress_chars("=> "),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+
+    let needs_tokens = options.count_tokens || options.max_tokens.is_some();
+    let mut total_files = 0;
+    let mut total_tokens = 0;
+    let mut total_saved = 0;
+    let mut skipped_files = 0;
+    let mut hashes: HashMap<blake3::Hash, PathBuf> = HashMap::new();
+
+    let mut spans = Vec::new();
+    let window = options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let read_errors = render_in_order(matched, options, group, window, &progress, |file| {
+        let start = if spans.is_empty() {
+            call_start
+        } else {
+            out.written()
+        };
+        if let Some(original) = file.hash.and_then(|hash| hashes.get(&hash)) {
+            if json {
+                out.start_element()?;
+            }
+            info!(
+                "{} is a duplicate of {}",
+                file.display_path.display(),
+                original.display()
+            );
+            total_files += 1;
+            print_duplicate(out, &file, original, options, group)?;
+            spans.push(start..out.written());
+            return Ok(());
+        }
+        if let (Some(tokens), Some(max)) = (file.tokens, options.max_tokens) {
+            if total_tokens + tokens > max {
+                warn!(
+                    event = "skipped", path:% = file.display_path.display(), reason = "token_budget";
+                    "Skipping {} ({} tokens): would exceed token budget of {}",
+                    file.display_path.display(),
+                    tokens,
+                    max
+                );
+                skipped_files += 1;
+                return Ok(());
+            }
+        }
+        if json {
+            out.start_element()?;
+        }
+        total_files += 1;
+        total_tokens += file.tokens.unwrap_or(0);
+        total_saved += file.tokens_saved;
+        out.write_all(&file.rendered)?;
+        spans.push(start..out.written());
+        if let Some(hash) = file.hash {
+            hashes.insert(hash, file.display_path);
+        }
+        Ok(())
+    })?;
+    progress.finish_and_clear();
+
+    if xml_root.is_some() {
+        writeln!(out, "</root>")?;
+    }
+
+    // Summary lines would land outside the root of a JSON or XML document, so they go to the
+    // log instead
+    let structured = json || wrap_xml;
+    if needs_tokens {
+        if structured {
+            info!("Total: {} files, {} tokens", total_files, total_tokens);
+        } else {
+            writeln!(out, "Total: {} files, {} tokens", total_files, total_tokens)?;
+        }
+    }
+    if needs_tokens && options.strip_comments {
+        if structured {
+            info!("Stripping comments saved {} tokens", total_saved);
+        } else {
+            writeln!(out, "Stripping comments saved {} tokens", total_saved)?;
+        }
+    }
+    if options.max_tokens.is_some() {
+        if structured {
+            info!("Skipped {} files due to token budget", skipped_files);
+        } else {
+            writeln!(out, "Skipped {} files due to token budget", skipped_files)?;
+        }
+    }
+    out.flush()?;
+    Ok(Printed {
+        tokens: total_tokens,
+        read_errors,
+        spans,
+    })
+}
+
+/// Splits a plain or markdown `document` into parts of at most `max_tokens` tokens each,
+/// headed `# Part 2 of 5`, cutting only where one of `file_spans` (from
+/// [`Stats::file_spans`](crate::Stats::file_spans)) starts so that no file is broken up. A
+/// file too large for a part on its own gets a part to itself.
+pub fn split_parts(document: &str, file_spans: &[Range<usize>], max_tokens: usize) -> Vec<String> {
+    let mut cuts: Vec<usize> = file_spans
+        .iter()
+        .map(|span| span.start)
+        .filter(|&start| start > 0 && start < document.len() && document.is_char_boundary(start))
+        .collect();
+    cuts.dedup();
+    let starts = std::iter::once(0).chain(cuts.iter().copied());
+    let ends = cuts.iter().copied().chain(std::iter::once(document.len()));
+    let segments: Vec<&str> = starts
+        .zip(ends)
+        .map(|(start, end)| &document[start..end])
+        .collect();
+
+    // Leave room for the header, sized for the widest part number
+    let header_tokens = count_tokens(&format!("# Part {0} of {0}\n\n", segments.len()));
+    let budget = max_tokens.saturating_sub(header_tokens);
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for segment in segments {
+        let tokens = count_tokens(segment);
+        if !current.is_empty() && current_tokens + tokens > budget {
+            parts.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+        if tokens > budget {
+            warn!(
+                "A file of {} tokens doesn't fit in a {}-token part; it gets a part to itself",
+                tokens, max_tokens
+            );
+        }
+        current.push_str(segment);
+        current_tokens += tokens;
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| format!("# Part {} of {}\n\n{}", i + 1, count, part))
+        .collect()
+}
+
+/// The plain parts of a relative file name, dropping any `..`, root, or prefix so it can't
+/// point outside the directory it is written to.
+fn normal_components(name: &Path) -> impl Iterator<Item = &OsStr> {
+    name.components().filter_map(|component| match component {
+        Component::Normal(part) => Some(part),
+        _ => None,
+    })
+}
+
+/// Writes the matched files into a zip archive at `path`, under their relative paths, and
+/// returns how many were written. Each is processed like printed contents, see
+/// [`write_split_output`].
+pub fn write_bundle(
+    path: &Path,
+    files: &[(PathBuf, MatchedFile)],
+    options: &SearchOptions,
+) -> Result<usize, ContextError> {
+    let file = File::create(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to create bundle {}: {}", path.display(), e),
+        )
+    })?;
+    let mut bundle = ZipWriter::new(BufWriter::new(file));
+    let entry_options =
+        SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    let mut written = 0;
+    for (name, file) in files {
+        let Some(contents) = output_file_contents(name, file, options) else {
+            continue;
+        };
+        // Zip entry names always use forward slashes
+        let name: Vec<String> = normal_components(name)
+            .map(|part| part.to_string_lossy().to_string())
+            .collect();
+        bundle.start_file(name.join("/"), entry_options)?;
+        bundle.write_all(&contents)?;
+        written += 1;
+    }
+    bundle.finish()?.flush()?;
+    Ok(written)
+}
+
+/// Copies the matched files into `dir` under the given relative names for `--split-output`,
+/// processed exactly like printed contents (see [`process_file`]). Binary files are copied
+/// unchanged with `--include-binary` and skipped otherwise. Returns the number written.
+pub fn write_split_output(
+    dir: &Path,
+    files: &[(PathBuf, MatchedFile)],
+    options: &SearchOptions,
+) -> Result<usize, ContextError> {
+    let mut written = 0;
+    for (name, file) in files {
+        let target: PathBuf = dir.join(normal_components(name).collect::<PathBuf>());
+        let Some(contents) = output_file_contents(name, file, options) else {
+            continue;
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Failed to create {}: {}", parent.display(), e),
+                )
+            })?;
+        }
+        fs::write(&target, contents).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to write {}: {}", target.display(), e),
+            )
+        })?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// The contents of `file`, named `name`, as written to a bundle or split output: processed
+/// like printed contents, or the raw bytes of a binary file with `--include-binary`. Skipped
+/// binary files and read errors, which are logged, give `None`.
+fn output_file_contents(
+    name: &Path,
+    file: &MatchedFile,
+    options: &SearchOptions,
+) -> Option<Vec<u8>> {
+    let contents = match sniff_binary(&file.path, options.strict_utf8) {
+        Ok(true) if !options.include_binary => {
+            log_skip(name, &SkipReason::Binary);
+            return None;
+        }
+        Ok(true) => fs::read(&file.path).inspect_err(|e| {
+            error!(
+                event = "skipped", path:% = name.display(), reason = "read_error";
+                "Error reading file {}: {}", file.path.display(), e
+            );
+        }),
+        // Read errors are logged by `process_file`
+        Ok(false) => process_file(file, options)
+            .map(|processed| processed.map(|processed| processed.contents.into_bytes()))
+            .map(Option::unwrap_or_default),
+        Err(e) => {
+            error!(
+                event = "skipped", path:% = name.display(), reason = "read_error";
+                "Error reading file {}: {}", file.path.display(), e
+            );
+            Err(e)
+        }
+    };
+    contents.ok()
+}
@@ -0,0 +1,125 @@
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::language::detect_language;
+use crate::search::MatchedFile;
+
+/// Marker files and the project type their presence in the root indicates.
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "Rust (Cargo)"),
+    ("package.json", "JavaScript (npm)"),
+    ("deno.json", "Deno"),
+    ("go.mod", "Go (modules)"),
+    ("pyproject.toml", "Python"),
+    ("setup.py", "Python"),
+    ("requirements.txt", "Python"),
+    ("pom.xml", "Java (Maven)"),
+    ("build.gradle", "JVM (Gradle)"),
+    ("build.gradle.kts", "JVM (Gradle)"),
+    ("Gemfile", "Ruby (Bundler)"),
+    ("composer.json", "PHP (Composer)"),
+    ("Package.swift", "Swift (SwiftPM)"),
+    ("mix.exs", "Elixir (Mix)"),
+    ("pubspec.yaml", "Dart (pub)"),
+    ("CMakeLists.txt", "C/C++ (CMake)"),
+    ("Makefile", "Make"),
+    ("Dockerfile", "Docker"),
+];
+
+/// Detects the kinds of project in `root` from well-known marker files such as `Cargo.toml`
+/// or `package.json`, in the order of [`PROJECT_MARKERS`]. Polyglot repositories return
+/// several.
+pub fn detect_project_type(root: &Path) -> Vec<String> {
+    let mut types: Vec<String> = Vec::new();
+    for (marker, project_type) in PROJECT_MARKERS {
+        if root.join(marker).is_file() && !types.iter().any(|t| t == project_type) {
+            types.push(project_type.to_string());
+        }
+    }
+    types
+}
+
+/// Counts newline-terminated lines, plus a final unterminated one. Binary files have none.
+fn count_lines(path: &Path) -> usize {
+    let Ok(bytes) = fs::read(path) else {
+        return 0;
+    };
+    if bytes.contains(&0) {
+        return 0;
+    }
+    let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+    newlines + usize::from(bytes.last().is_some_and(|&b| b != b'\n'))
+}
+
+/// The `--overview` summary: project types, and file and line counts per language.
+pub(crate) struct Overview {
+    pub(crate) project_types: Vec<String>,
+    pub(crate) files: usize,
+    pub(crate) lines: usize,
+    /// Language to file count and lines
+    pub(crate) by_language: BTreeMap<String, (usize, usize)>,
+}
+
+impl Overview {
+    pub(crate) fn collect(matched: &[MatchedFile], root: &Path) -> Self {
+        let lines: Vec<usize> = matched
+            .par_iter()
+            .map(|file| count_lines(&file.path))
+            .collect();
+
+        let mut by_language: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for (file, lines) in matched.iter().zip(&lines) {
+            // Grouping by name keeps extensionless files like `Dockerfile` out of "(none)"
+            let language = file
+                .language
+                .clone()
+                .or_else(|| detect_language(&file.path, None))
+                .or_else(|| {
+                    file.path
+                        .extension()
+                        .map(|ext| ext.to_string_lossy().to_lowercase())
+                })
+                .unwrap_or_else(|| "(none)".to_string());
+            let entry = by_language.entry(language).or_default();
+            entry.0 += 1;
+            entry.1 += lines;
+        }
+
+        Overview {
+            project_types: detect_project_type(root),
+            files: matched.len(),
+            lines: lines.iter().sum(),
+            by_language,
+        }
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut output = String::new();
+        let project_type = if self.project_types.is_empty() {
+            "unknown".to_string()
+        } else {
+            self.project_types.join(", ")
+        };
+        let _ = writeln!(output, "Project type: {}", project_type);
+        let _ = writeln!(output, "Files: {}, {} lines", self.files, self.lines);
+        if self.by_language.is_empty() {
+            return output;
+        }
+
+        let mut languages: Vec<_> = self.by_language.iter().collect();
+        languages.sort_by_key(|(_, (files, lines))| Reverse((*lines, *files)));
+        let _ = writeln!(output, "Languages:");
+        for (language, (files, lines)) in languages {
+            let _ = writeln!(
+                output,
+                "  {:<12} {:>6} files {:>8} lines",
+                language, files, lines
+            );
+        }
+        output
+    }
+}
@@ -0,0 +1,1612 @@
+use flate2::read::GzDecoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use rayon::prelude::*;
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error;
+use std::fmt::{self, Write as _};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use zip::ZipArchive;
+
+use crate::error::ContextError;
+
+/// Code hosts whose repository URLs and archive downloads are understood.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepoHost {
+    GitHub,
+    GitLab,
+    Bitbucket,
+}
+
+#[derive(Debug)]
+pub struct RepoInfo {
+    pub host: RepoHost,
+    pub repo_url: String,
+    /// Branch, tag, or commit SHA from a `/tree/<ref>` URL
+    pub branch_name: Option<String>,
+    pub folder_path: Option<String>,
+}
+
+/// Checks a `--github-host` value such as `github.mycorp.com` and returns it normalized.
+pub fn check_github_host(host: &str) -> Result<String, ContextError> {
+    let host = host.trim().trim_end_matches('/').to_lowercase();
+    if host.is_empty()
+        || host.contains("://")
+        || host.contains('/')
+        || host.contains(char::is_whitespace)
+    {
+        return Err(ContextError::InvalidGithubHost(host));
+    }
+    if host == "gitlab.com" || host == "bitbucket.org" || !host.contains("git") {
+        warn!(
+            "'{}' does not look like a GitHub host; archive URLs will use GitHub's layout",
+            host
+        );
+    }
+    Ok(host)
+}
+
+/// Parses a repository URL or `owner/repo` shorthand like [`parse_repo_url`], for callers
+/// without a GitHub Enterprise host.
+pub fn parse_github_url(url: &str) -> Result<RepoInfo, ContextError> {
+    parse_repo_url(url, None)
+}
+
+/// Parses a repository URL on github.com, gitlab.com, bitbucket.org, or `github_host` (a
+/// GitHub Enterprise server, already checked with [`check_github_host`]). A GitHub
+/// shorthand such as `owner/repo@branch:path` is accepted too.
+pub fn parse_repo_url(url: &str, github_host: Option<&str>) -> Result<RepoInfo, ContextError> {
+    if !url.contains("://") {
+        return parse_repo_shorthand(url);
+    }
+    let parsed_url = Url::parse(url).map_err(|e| ContextError::InvalidGithubUrl(e.to_string()))?;
+
+    let host_str = parsed_url.host_str().unwrap_or_default();
+    let host = match host_str {
+        "github.com" => RepoHost::GitHub,
+        "gitlab.com" => RepoHost::GitLab,
+        "bitbucket.org" => RepoHost::Bitbucket,
+        _ if github_host == Some(host_str) => RepoHost::GitHub,
+        _ => return Err(ContextError::NotAGithubUrl(host_str.to_string())),
+    };
+
+    let path_segments: Vec<&str> = parsed_url
+        .path_segments()
+        .ok_or_else(|| ContextError::InvalidGithubUrl("No path segments".to_string()))?
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    // GitLab groups can nest, so the repository path runs up to the `-` separator
+    let (repo_segments, rest) = match host {
+        RepoHost::GitLab => match path_segments.iter().position(|s| *s == "-") {
+            Some(i) => (&path_segments[..i], &path_segments[i + 1..]),
+            None => (&path_segments[..], &[][..]),
+        },
+        RepoHost::GitHub | RepoHost::Bitbucket => {
+            path_segments.split_at(path_segments.len().min(2))
+        }
+    };
+
+    if repo_segments.len() < 2 {
+        return Err(ContextError::InvalidGithubUrl(
+            "URL doesn't contain a valid repository path".to_string(),
+        ));
+    }
+
+    let repo_url = format!("https://{}/{}", host_str, repo_segments.join("/"));
+
+    let tree_marker = match host {
+        RepoHost::GitHub | RepoHost::GitLab => "tree",
+        RepoHost::Bitbucket => "src",
+    };
+    let (branch_name, folder_path) = if rest.len() >= 2 && rest[0] == tree_marker {
+        let branch = Some(rest[1].to_string());
+        let folder = if rest.len() > 2 {
+            Some(rest[2..].join("/"))
+        } else {
+            None
+        };
+        (branch, folder)
+    } else {
+        (None, None)
+    };
+
+    Ok(RepoInfo {
+        host,
+        repo_url,
+        branch_name,
+        folder_path,
+    })
+}
+
+/// Parses `owner/repo`, optionally followed by `@branch` and then `:path`, as a github.com
+/// repository. Git refs can't contain `:`, so the first one always starts the path.
+fn parse_repo_shorthand(spec: &str) -> Result<RepoInfo, ContextError> {
+    let malformed = || {
+        ContextError::InvalidGithubUrl(
+            "Not a URL or an owner/repo shorthand (optionally followed by @branch and :path)"
+                .to_string(),
+        )
+    };
+    let (repo, folder) = match spec.split_once(':') {
+        Some((repo, folder)) => (repo, Some(folder.trim_matches('/'))),
+        None => (spec, None),
+    };
+    let (repo, branch) = match repo.split_once('@') {
+        Some((repo, branch)) => (repo, Some(branch)),
+        None => (repo, None),
+    };
+    let Some((owner, name)) = repo.split_once('/') else {
+        return Err(malformed());
+    };
+    // GitHub owners are letters, digits, and inner hyphens; repository names also allow
+    // `.` and `_`
+    let valid_owner = !owner.is_empty()
+        && !owner.starts_with('-')
+        && owner.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_name = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid_owner || !valid_name || branch == Some("") {
+        return Err(malformed());
+    }
+
+    Ok(RepoInfo {
+        host: RepoHost::GitHub,
+        repo_url: format!("https://github.com/{}/{}", owner, name),
+        branch_name: branch.map(str::to_string),
+        folder_path: folder
+            .filter(|folder| !folder.is_empty())
+            .map(str::to_string),
+    })
+}
+
+/// Returns the `owner/repo` (or `group/subgroup/repo`) part of a normalized repository URL.
+pub(crate) fn repo_path(repo_url: &str) -> String {
+    Url::parse(repo_url)
+        .map(|url| url.path().trim_matches('/').to_string())
+        .unwrap_or_default()
+}
+
+pub(crate) fn repo_host_name(repo_url: &str) -> Option<String> {
+    Url::parse(repo_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    #[value(name = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    /// Guesses the format of a response from its `Content-Type`, then from the URL suffix.
+    pub(crate) fn detect(content_type: Option<&str>, url: &str) -> Option<Self> {
+        let content_type = content_type.unwrap_or_default();
+        if content_type.contains("gzip") || content_type.contains("x-gtar") {
+            return Some(ArchiveFormat::TarGz);
+        }
+        if content_type.contains("zip") {
+            return Some(ArchiveFormat::Zip);
+        }
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds the archive URL for `git_ref`, which every host accepts as a branch, tag, or
+/// commit SHA.
+pub fn build_archive_url(
+    host: RepoHost,
+    repo_url: &str,
+    git_ref: &str,
+    format: ArchiveFormat,
+) -> String {
+    let extension = format.extension();
+    match host {
+        RepoHost::GitHub => format!("{}/archive/{}.{}", repo_url, git_ref, extension),
+        RepoHost::GitLab => {
+            let path = repo_path(repo_url);
+            let name = path.rsplit('/').next().unwrap_or_default();
+            format!(
+                "{}/-/archive/{}/{}-{}.{}",
+                repo_url, git_ref, name, git_ref, extension
+            )
+        }
+        RepoHost::Bitbucket => format!("{}/get/{}.{}", repo_url, git_ref, extension),
+    }
+}
+
+pub(crate) const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// A download refused or cut short by the server, e.g. an auth failure or rate limit. Along
+/// with [`NotFoundError`] and network errors it is recognized by [`is_download_error`].
+#[derive(Debug)]
+pub(crate) struct DownloadError(pub(crate) String);
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for DownloadError {}
+
+/// Whether `err`, or an error it wraps, came from talking to a code host or download server
+/// rather than from the local machine.
+pub fn is_download_error(err: &(dyn error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if matches!(
+            err.downcast_ref::<ContextError>(),
+            Some(ContextError::Download(_))
+        ) || err.is::<reqwest::Error>()
+            || err.is::<NotFoundError>()
+            || err.is::<ServerError>()
+            || err.is::<DownloadError>()
+        {
+            return true;
+        }
+        current = err.source();
+    }
+    false
+}
+
+/// Returned when an archive URL answers 404, so callers can retry another branch.
+#[derive(Debug)]
+pub struct NotFoundError(pub(crate) String);
+
+impl fmt::Display for NotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for NotFoundError {}
+
+/// Returned for 5xx and 429 responses and for bodies that arrive incomplete or damaged, which
+/// every download retries up to `--retries` times.
+#[derive(Debug)]
+pub(crate) struct ServerError(pub(crate) String);
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for ServerError {}
+
+/// Builds a readable error for a 403 or 429 caused by an exhausted rate limit, which GitHub
+/// signals with `X-RateLimit-Remaining: 0` or `Retry-After`. Other responses give `None`.
+pub(crate) fn rate_limit_error(
+    response: &Response,
+    url: &str,
+    token: Option<&str>,
+) -> Option<DownloadError> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    // `X-RateLimit-Reset` is a Unix timestamp, `Retry-After` a number of seconds
+    let reset = match (header("x-ratelimit-remaining"), header("retry-after")) {
+        (Some(0), _) => header("x-ratelimit-reset"),
+        (_, Some(after)) => Some(now + after),
+        _ => return None,
+    };
+
+    let mut message = format!("Rate limit exceeded ({}) requesting {}", status, url);
+    if let Some(reset) = reset {
+        let minutes = reset.saturating_sub(now).div_ceil(60);
+        let _ = write!(
+            message,
+            "; it resets at {:02}:{:02} UTC (in {} minute{})",
+            reset % 86400 / 3600,
+            reset % 3600 / 60,
+            minutes,
+            if minutes == 1 { "" } else { "s" }
+        );
+    }
+    message.push_str(if token.is_none() {
+        ". Pass --token or set GITHUB_TOKEN to get a much higher limit"
+    } else {
+        ". The token's quota is used up; wait for the reset or use another token"
+    });
+    Some(DownloadError(message))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RepoMetadata {
+    /// Reported by GitHub and GitLab
+    pub(crate) default_branch: Option<String>,
+    /// Reported by Bitbucket
+    pub(crate) mainbranch: Option<BranchRef>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BranchRef {
+    pub(crate) name: String,
+}
+
+pub(crate) fn http_client() -> reqwest::Result<Client> {
+    Client::builder().user_agent(USER_AGENT).build()
+}
+
+/// Asks the host's API for the repository's default branch.
+pub fn resolve_default_branch(
+    host: RepoHost,
+    repo_url: &str,
+    token: Option<&str>,
+) -> Result<String, ContextError> {
+    fetch_default_branch(host, repo_url, token).map_err(ContextError::Download)
+}
+
+fn fetch_default_branch(
+    host: RepoHost,
+    repo_url: &str,
+    token: Option<&str>,
+) -> Result<String, Box<dyn error::Error + Send + Sync>> {
+    let path = repo_path(repo_url);
+    let api_url = match host {
+        RepoHost::GitHub => github_api_url(repo_url),
+        RepoHost::GitLab => format!(
+            "https://gitlab.com/api/v4/projects/{}",
+            url::form_urlencoded::byte_serialize(path.as_bytes()).collect::<String>()
+        ),
+        RepoHost::Bitbucket => format!("https://api.bitbucket.org/2.0/repositories/{}", path),
+    };
+
+    let mut request = http_client()?.get(&api_url);
+    if host == RepoHost::GitHub {
+        request = request.header("Accept", "application/vnd.github+json");
+    }
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send()?;
+    if let Some(e) = rate_limit_error(&response, &api_url, token) {
+        return Err(e.into());
+    }
+    let metadata: RepoMetadata = response.error_for_status()?.json()?;
+    metadata
+        .default_branch
+        .or(metadata.mainbranch.map(|branch| branch.name))
+        .ok_or_else(|| {
+            DownloadError("Repository metadata has no default branch".to_string()).into()
+        })
+}
+
+/// The REST API URL of a GitHub repository, e.g. `https://api.github.com/repos/owner/repo`.
+fn github_api_url(repo_url: &str) -> String {
+    let path = repo_path(repo_url);
+    match repo_host_name(repo_url).as_deref() {
+        Some("github.com") | None => format!("https://api.github.com/repos/{}", path),
+        // GitHub Enterprise serves the REST API under /api/v3 on its own host
+        Some(enterprise) => format!("https://{}/api/v3/repos/{}", enterprise, path),
+    }
+}
+
+/// The raw contents URL of a file in a GitHub repository at `git_ref`.
+fn github_raw_url(
+    repo_url: &str,
+    git_ref: &str,
+    file: &str,
+) -> Result<Url, Box<dyn error::Error + Send + Sync>> {
+    let path = repo_path(repo_url);
+    let mut url = match repo_host_name(repo_url).as_deref() {
+        Some("github.com") | None => Url::parse("https://raw.githubusercontent.com")?,
+        Some(enterprise) => Url::parse(&format!("https://{}/raw", enterprise))?,
+    };
+    url.path_segments_mut()
+        .map_err(|_| "Raw URL cannot have a path")?
+        .pop_if_empty()
+        .extend(path.split('/'))
+        .push(git_ref)
+        .extend(file.split('/'));
+    Ok(url)
+}
+
+#[derive(Deserialize)]
+struct GitTree {
+    sha: String,
+    tree: Vec<GitTreeEntry>,
+    /// Set when the repository has too many entries for a single response
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct GitTreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Downloads only the files under `folder` of a GitHub repository at `git_ref` into
+/// `target_folder`, keeping their paths, for `--sparse`. Returns `target_folder`, laid out
+/// like an extracted archive so `folder` can be searched inside it.
+///
+/// The file list comes from a single git trees API call and each file from its raw URL. A
+/// cached copy records the tree's hash and, given a token, is revalidated against it;
+/// without a token it is reused as is, like a cached archive.
+pub fn download_sparse(
+    repo_url: &str,
+    git_ref: &str,
+    folder: &str,
+    target_folder: &Path,
+    options: DownloadOptions,
+) -> Result<PathBuf, ContextError> {
+    let DownloadOptions {
+        token, cache_mode, ..
+    } = options;
+    let cached = match cache_mode {
+        CacheMode::Use => read_cache_entry(target_folder),
+        CacheMode::Refresh | CacheMode::Bypass => None,
+    };
+    if cached.is_some() && token.is_none() {
+        info!(
+            "Using cached sparse download in {}",
+            target_folder.display()
+        );
+        return Ok(target_folder.to_path_buf());
+    }
+
+    let client = http_client().map_err(|e| ContextError::Download(e.into()))?;
+    let tree_url = format!(
+        "{}/git/trees/{}?recursive=1",
+        github_api_url(repo_url),
+        git_ref
+    );
+    let tree: GitTree = with_retries(options.retries, || {
+        github_api_get(&client, &tree_url, token, || {
+            format!("No ref '{}' found at {}", git_ref, repo_url)
+        })
+    })
+    .map_err(ContextError::Download)?;
+    if tree.truncated {
+        return Err(ContextError::Download(Box::new(DownloadError(
+            "The repository is too large to list through the API; download it without --sparse"
+                .to_string(),
+        ))));
+    }
+    if cached.is_some_and(|entry| entry.etag.as_deref() == Some(tree.sha.as_str())) {
+        info!(
+            "Cached sparse download in {} is up to date",
+            target_folder.display()
+        );
+        return Ok(target_folder.to_path_buf());
+    }
+
+    let prefix = format!("{}/", folder.trim_matches('/'));
+    let files: Vec<&str> = tree
+        .tree
+        .iter()
+        .filter(|entry| entry.kind == "blob" && entry.path.starts_with(&prefix))
+        .map(|entry| entry.path.as_str())
+        // Same rule as archive entries: nothing may land outside the target
+        .filter(|path| {
+            Path::new(path)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        })
+        .filter(|path| !skip_reserved_name(path, Path::new(path)))
+        .collect();
+    info!("Downloading {} files under {}", files.len(), prefix);
+
+    if cache_mode != CacheMode::Bypass && target_folder.exists() {
+        fs::remove_dir_all(target_folder)?;
+    }
+    fs::create_dir_all(target_folder)?;
+    download_raw_files(&client, repo_url, git_ref, &files, target_folder, options)?;
+
+    if cache_mode != CacheMode::Bypass {
+        let entry = CacheEntry {
+            etag: Some(tree.sha),
+            root: PathBuf::new(),
+        };
+        fs::write(
+            target_folder.join(CACHE_MARKER),
+            serde_json::to_string(&entry).map_err(io::Error::from)?,
+        )?;
+    }
+    Ok(target_folder.to_path_buf())
+}
+
+/// Fetches and parses a GitHub REST API response, failing with a [`NotFoundError`] described
+/// by `not_found` on a 404.
+fn github_api_get<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    token: Option<&str>,
+    not_found: impl FnOnce() -> String,
+) -> Result<T, Box<dyn error::Error + Send + Sync>> {
+    let mut request = client
+        .get(url)
+        .header("Accept", "application/vnd.github+json");
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send()?;
+    if let Some(e) = rate_limit_error(&response, url, token) {
+        return Err(e.into());
+    }
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(NotFoundError(not_found()).into());
+    }
+    Ok(check_status(response, url)?.json()?)
+}
+
+/// Fails with a retryable [`ServerError`] for a 5xx or 429 response, or with reqwest's error
+/// for any other unsuccessful one.
+fn check_status(
+    response: Response,
+    url: &str,
+) -> Result<Response, Box<dyn error::Error + Send + Sync>> {
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(Box::new(ServerError(format!(
+            "Requesting {} failed with HTTP {}",
+            url, status
+        ))));
+    }
+    Ok(response.error_for_status()?)
+}
+
+/// Calls `attempt` until it succeeds, fails in a way [`is_retryable`] rejects, or has been
+/// retried `retries` times, waiting 1s, 2s, 4s, ... (at most 32s) in between.
+fn with_retries<T>(
+    retries: u32,
+    mut attempt: impl FnMut() -> Result<T, Box<dyn error::Error + Send + Sync>>,
+) -> Result<T, Box<dyn error::Error + Send + Sync>> {
+    let mut retried = 0;
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(e) if retried < retries && is_retryable(e.as_ref()) => {
+                retried += 1;
+                let delay = Duration::from_secs(1 << (retried - 1).min(5));
+                info!(
+                    "Download failed ({}), retrying in {}s (attempt {}/{})",
+                    e,
+                    delay.as_secs(),
+                    retried,
+                    retries
+                );
+                thread::sleep(delay);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Downloads `files` of a GitHub repository at `git_ref` from their raw URLs into
+/// `target_folder`, keeping their paths.
+fn download_raw_files(
+    client: &Client,
+    repo_url: &str,
+    git_ref: &str,
+    files: &[&str],
+    target_folder: &Path,
+    options: DownloadOptions,
+) -> Result<(), ContextError> {
+    let token = options.token;
+    let progress = if options.show_progress {
+        ProgressBar::new(files.len() as u64).with_style(
+            ProgressStyle::with_template("Downloading [{bar:40}] {pos}/{len} files")
+                .expect("progress template is valid")
+                .progress_chars("=> "),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+    files
+        .par_iter()
+        .try_for_each(|file| -> Result<(), ContextError> {
+            let fetch = || -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+                let url = github_raw_url(repo_url, git_ref, file)?;
+                let mut request = client.get(url.clone());
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                let response = request.send()?;
+                if let Some(e) = rate_limit_error(&response, url.as_str(), token) {
+                    return Err(e.into());
+                }
+                Ok(check_status(response, url.as_str())?.bytes()?.to_vec())
+            };
+            let contents = with_retries(options.retries, fetch).map_err(|e| {
+                ContextError::Download(Box::new(DownloadError(format!(
+                    "Failed to download {}: {}",
+                    file, e
+                ))))
+            })?;
+            let path = long_path(&target_folder.join(file));
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, contents).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Failed to write {}: {}", path.display(), e),
+                )
+            })?;
+            progress.inc(1);
+            Ok(())
+        })?;
+    progress.finish_and_clear();
+    Ok(())
+}
+
+/// GitHub's compare API lists at most this many changed files.
+const COMPARE_FILE_LIMIT: usize = 300;
+
+#[derive(Deserialize)]
+struct GitCompare {
+    files: Vec<ChangedFile>,
+}
+
+/// A file that differs between the two refs of a [`Comparison`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangedFile {
+    #[serde(rename = "filename")]
+    pub path: String,
+    /// `added`, `removed`, `modified`, `renamed`, `copied`, or `changed`
+    pub status: String,
+    pub additions: u64,
+    pub deletions: u64,
+    /// The old path of a renamed file
+    pub previous_filename: Option<String>,
+}
+
+/// The files changed between two refs, as downloaded by [`download_compare`].
+#[derive(Debug)]
+pub struct Comparison {
+    /// Directory holding the changed files as they are at the head ref
+    pub root: PathBuf,
+    pub base: String,
+    pub head: String,
+    pub files: Vec<ChangedFile>,
+}
+
+impl Comparison {
+    /// Summarizes the changes like `git diff --stat`: one line per file with its changed
+    /// line count and a bar of `+` and `-`, then the totals.
+    pub fn stat(&self) -> String {
+        const BAR_WIDTH: u64 = 40;
+        let names: Vec<String> = self
+            .files
+            .iter()
+            .map(
+                |file| match (&file.previous_filename, file.status.as_str()) {
+                    (Some(previous), "renamed") => format!("{} => {}", previous, file.path),
+                    (_, "removed") => format!("{} (removed)", file.path),
+                    _ => file.path.clone(),
+                },
+            )
+            .collect();
+        let name_width = names.iter().map(|name| name.chars().count()).max();
+        let most_changes = self
+            .files
+            .iter()
+            .map(|file| file.additions + file.deletions)
+            .max()
+            .unwrap_or(0);
+        let count_width = most_changes.to_string().len();
+
+        let mut stat = String::new();
+        for (file, name) in self.files.iter().zip(&names) {
+            let changes = file.additions + file.deletions;
+            // Scaled down only when the largest change wouldn't fit
+            let (plus, minus) = if most_changes > BAR_WIDTH {
+                let scale = |n: u64| (n * BAR_WIDTH).div_ceil(most_changes);
+                (scale(file.additions), scale(file.deletions))
+            } else {
+                (file.additions, file.deletions)
+            };
+            let line = format!(
+                " {:name_width$} | {:>count_width$} {}{}",
+                name,
+                changes,
+                "+".repeat(plus as usize),
+                "-".repeat(minus as usize),
+                name_width = name_width.unwrap_or(0),
+            );
+            stat.push_str(line.trim_end());
+            stat.push('\n');
+        }
+        let additions: u64 = self.files.iter().map(|file| file.additions).sum();
+        let deletions: u64 = self.files.iter().map(|file| file.deletions).sum();
+        let plural = |n: u64| if n == 1 { "" } else { "s" };
+        let _ = writeln!(
+            stat,
+            " {} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+            self.files.len(),
+            plural(self.files.len() as u64),
+            additions,
+            plural(additions),
+            deletions,
+            plural(deletions)
+        );
+        stat
+    }
+}
+
+/// Downloads the files of a GitHub repository that changed between `base` and `head` into
+/// `target_folder`, as they are at `head`, for `--base`. With a `folder`, only changes under
+/// it are kept.
+///
+/// The file list comes from the compare API, which diffs against the merge base like
+/// `git diff base...head`, and each file from its raw URL. Removed files are listed but not
+/// downloaded. Nothing is cached.
+pub fn download_compare(
+    repo_url: &str,
+    base: &str,
+    head: &str,
+    folder: Option<&str>,
+    target_folder: &Path,
+    options: DownloadOptions,
+) -> Result<Comparison, ContextError> {
+    let token = options.token;
+    let client = http_client().map_err(|e| ContextError::Download(e.into()))?;
+    let compare_url = format!("{}/compare/{}...{}", github_api_url(repo_url), base, head);
+    let compare: GitCompare = with_retries(options.retries, || {
+        github_api_get(&client, &compare_url, token, || {
+            format!(
+                "Could not compare '{}' with '{}' at {}",
+                base, head, repo_url
+            )
+        })
+    })
+    .map_err(ContextError::Download)?;
+    if compare.files.len() >= COMPARE_FILE_LIMIT {
+        warn!(
+            "GitHub lists at most {} changed files; the context may be missing some",
+            COMPARE_FILE_LIMIT
+        );
+    }
+
+    let prefix = folder.map(|folder| format!("{}/", folder.trim_matches('/')));
+    let files: Vec<ChangedFile> = compare
+        .files
+        .into_iter()
+        .filter(|file| {
+            prefix
+                .as_ref()
+                .is_none_or(|prefix| file.path.starts_with(prefix))
+        })
+        .collect();
+    let downloads: Vec<&str> = files
+        .iter()
+        .filter(|file| file.status != "removed")
+        .map(|file| file.path.as_str())
+        // Same rule as archive entries: nothing may land outside the target
+        .filter(|path| {
+            Path::new(path)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        })
+        .filter(|path| !skip_reserved_name(path, Path::new(path)))
+        .collect();
+    info!(
+        "Downloading {} of {} files changed from {} to {}",
+        downloads.len(),
+        files.len(),
+        base,
+        head
+    );
+
+    fs::create_dir_all(target_folder)?;
+    download_raw_files(&client, repo_url, head, &downloads, target_folder, options)?;
+    Ok(Comparison {
+        root: target_folder.to_path_buf(),
+        base: base.to_string(),
+        head: head.to_string(),
+        files,
+    })
+}
+
+/// How `download_and_extract_repo` treats previously extracted archives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Reuse a cached extraction when one exists
+    Use,
+    /// Always download into the cache, replacing any existing extraction
+    Refresh,
+    /// Skip the cache entirely and extract into the working directory
+    Bypass,
+}
+
+pub(crate) const CACHE_MARKER: &str = ".llm-context-cache.json";
+
+/// Written next to a cached extraction once it completed successfully.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CacheEntry {
+    pub(crate) etag: Option<String>,
+    /// Search root relative to the cache directory
+    pub(crate) root: PathBuf,
+}
+
+/// Returns the cache directory for an archive URL, e.g.
+/// `~/.cache/llm-context-builder/github.com_owner_repo_archive_main.zip`.
+pub fn cache_dir_for(archive_url: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| {
+        dir.join(env!("CARGO_PKG_NAME"))
+            .join(cache_key(archive_url))
+    })
+}
+
+/// `archive_url`, which names the repository and ref, as a file name.
+fn cache_key(archive_url: &str) -> String {
+    archive_url
+        .trim_start_matches("https://")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn read_cache_entry(target_folder: &Path) -> Option<CacheEntry> {
+    let contents = fs::read_to_string(target_folder.join(CACHE_MARKER)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// How a repository archive is fetched.
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadOptions<'a> {
+    pub token: Option<&'a str>,
+    pub cache_mode: CacheMode,
+    pub show_progress: bool,
+    /// Write the archive to a temporary file instead of buffering it in memory
+    pub spill_to_disk: bool,
+    /// Forces the archive format; otherwise it is detected from the response
+    pub archive_format: Option<ArchiveFormat>,
+    /// Extra attempts after a retryable failure, with exponential backoff
+    pub retries: u32,
+    /// Download into a file in the cache directory that survives an interruption, and
+    /// continue it with an HTTP range request on the next attempt or run
+    pub resume: bool,
+    /// Threads writing zip entries, see [`extract_archive`]. A downloaded archive already in
+    /// memory is shared by them; one spilled to disk is read back into memory first.
+    pub extract_jobs: usize,
+}
+
+/// A downloaded archive held either in memory or in a file on disk.
+pub(crate) enum ArchiveData {
+    Memory(Vec<u8>),
+    File(File),
+}
+
+impl ArchiveData {
+    /// Extracts the archive like [`extract_archive`], without copying an archive in memory.
+    fn extract(
+        self,
+        format: ArchiveFormat,
+        target_folder: &Path,
+        jobs: usize,
+    ) -> Result<PathBuf, ContextError> {
+        match self {
+            ArchiveData::Memory(data) => extract_archive_bytes(&data, format, target_folder, jobs),
+            ArchiveData::File(file) => {
+                extract_archive(io::BufReader::new(file), format, target_folder, jobs)
+            }
+        }
+    }
+}
+
+pub fn download_and_extract_repo(
+    archive_url: &str,
+    target_folder: &Path,
+    options: DownloadOptions,
+) -> Result<PathBuf, ContextError> {
+    let DownloadOptions {
+        token, cache_mode, ..
+    } = options;
+    let cached = match cache_mode {
+        CacheMode::Use => read_cache_entry(target_folder),
+        CacheMode::Refresh | CacheMode::Bypass => None,
+    };
+    if let Some(entry) = &cached {
+        // Without a token, revalidating would only burn the anonymous rate limit
+        if token.is_none() || entry.etag.is_none() {
+            info!("Using cached extraction in {}", target_folder.display());
+            return Ok(target_folder.join(&entry.root));
+        }
+    }
+
+    let cached_etag = cached.as_ref().and_then(|entry| entry.etag.as_deref());
+    let partial = options
+        .resume
+        .then(|| partial_download_path(archive_url))
+        .flatten();
+    let fetched = with_retries(options.retries, || {
+        fetch_archive(archive_url, options, cached_etag, partial.as_deref())
+    })
+    .map_err(ContextError::Download)?;
+    let Some(FetchedArchive {
+        archive,
+        format,
+        etag,
+    }) = fetched
+    else {
+        let root = cached.map(|entry| entry.root).unwrap_or_default();
+        info!(
+            "Cached extraction in {} is up to date",
+            target_folder.display()
+        );
+        return Ok(target_folder.join(root));
+    };
+
+    let jobs = options.extract_jobs;
+    if cache_mode == CacheMode::Bypass {
+        let root = archive.extract(format, target_folder, jobs)?;
+        if let Some(partial) = &partial {
+            remove_partial_download(partial);
+        }
+        return Ok(root);
+    }
+
+    // Start from an empty directory so stale files from an older extraction can't linger
+    if target_folder.exists() {
+        fs::remove_dir_all(target_folder)?;
+    }
+    let root = archive.extract(format, target_folder, jobs)?;
+    if let Some(partial) = &partial {
+        remove_partial_download(partial);
+    }
+    let entry = CacheEntry {
+        etag,
+        root: root
+            .strip_prefix(target_folder)
+            .unwrap_or(Path::new(""))
+            .to_path_buf(),
+    };
+    fs::write(
+        target_folder.join(CACHE_MARKER),
+        serde_json::to_string(&entry).map_err(io::Error::from)?,
+    )?;
+    Ok(root)
+}
+
+/// A downloaded archive, ready to extract.
+pub(crate) struct FetchedArchive {
+    pub(crate) archive: ArchiveData,
+    pub(crate) format: ArchiveFormat,
+    pub(crate) etag: Option<String>,
+}
+
+/// Where `--resume` keeps the unfinished download of `archive_url`, e.g.
+/// `~/.cache/llm-context-builder/partial/github.com_owner_repo_archive_main.zip`.
+///
+/// It is named after the URL, and so the repository and ref, rather than the extraction
+/// directory, which is a fresh temporary one with `--no-cache` or `--download-dir`. The
+/// `ETag` (or `Last-Modified`) it was started with is kept in a `.json` file beside it, and
+/// the download is only continued while the server still sends the same one.
+fn partial_download_path(archive_url: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| {
+        dir.join(env!("CARGO_PKG_NAME"))
+            .join("partial")
+            .join(cache_key(archive_url))
+    })
+}
+
+fn partial_info_path(partial: &Path) -> PathBuf {
+    let mut name = partial.as_os_str().to_os_string();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+/// What a partial download was started from, so it is only continued against the same file.
+#[derive(Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    /// Strong `ETag` or `Last-Modified` of the response, sent back as `If-Range`
+    validator: String,
+}
+
+/// The bytes already downloaded into `partial` and the validator to continue them with, when
+/// they belong to `archive_url`.
+fn resumable_download(partial: &Path, archive_url: &str) -> Option<(u64, String)> {
+    let info = fs::read_to_string(partial_info_path(partial)).ok()?;
+    let info: PartialDownload = serde_json::from_str(&info).ok()?;
+    let len = fs::metadata(partial).ok()?.len();
+    (info.url == archive_url && len > 0).then_some((len, info.validator))
+}
+
+fn remove_partial_download(partial: &Path) {
+    let _ = fs::remove_file(partial);
+    let _ = fs::remove_file(partial_info_path(partial));
+}
+
+/// The first byte of a `206` response, from `Content-Range: bytes 100-199/200`.
+fn content_range_start(response: &Response) -> Option<u64> {
+    let range = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.parse().ok()
+}
+
+/// Downloads `archive_url` once. Returns `None` when the server confirms `cached_etag` is
+/// still current.
+///
+/// With `partial`, the body is written to that file as it arrives, and a file left by an
+/// interrupted attempt is continued with a range request if the server still has the same
+/// archive. The download is checked against `Content-Length`, and a zip's central directory
+/// must be readable, before it is returned.
+pub(crate) fn fetch_archive(
+    archive_url: &str,
+    options: DownloadOptions,
+    cached_etag: Option<&str>,
+    partial: Option<&Path>,
+) -> Result<Option<FetchedArchive>, Box<dyn error::Error + Send + Sync>> {
+    let token = options.token;
+    let mut request = http_client()?.get(archive_url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    if let Some(etag) = cached_etag {
+        request = request.header("If-None-Match", etag);
+    }
+    let resume = partial.and_then(|partial| resumable_download(partial, archive_url));
+    if let Some((offset, validator)) = &resume {
+        info!("Resuming download of {} from byte {}", archive_url, offset);
+        request = request
+            .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+            .header(reqwest::header::IF_RANGE, validator.as_str());
+    }
+    let mut response = request.send()?;
+    if let (Some(partial), Some(_)) = (partial, &resume) {
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            info!("Server can't continue the partial download, starting over");
+            remove_partial_download(partial);
+            return fetch_archive(archive_url, options, cached_etag, Some(partial));
+        }
+    }
+    if response.status() == StatusCode::NOT_MODIFIED && cached_etag.is_some() {
+        return Ok(None);
+    }
+    // Checked before anything reads the body, which is a JSON error rather than an archive
+    if let Some(e) = rate_limit_error(&response, archive_url, token) {
+        return Err(e.into());
+    }
+    match response.status() {
+        status if status.is_success() => {}
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(Box::new(DownloadError(format!(
+                "Access denied ({}) downloading {}: check that the token is valid and can read the repository",
+                response.status(),
+                archive_url
+            ))));
+        }
+        StatusCode::NOT_FOUND => {
+            let hint = if token.is_none() {
+                " (private repositories need --token or GITHUB_TOKEN)"
+            } else {
+                ""
+            };
+            return Err(Box::new(NotFoundError(format!(
+                "Repository or ref not found: {}{}",
+                archive_url, hint
+            ))));
+        }
+        status if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+            return Err(Box::new(ServerError(format!(
+                "Downloading {} failed with HTTP {}",
+                archive_url, status
+            ))));
+        }
+        status => {
+            return Err(Box::new(DownloadError(format!(
+                "Downloading {} failed with HTTP {}",
+                archive_url, status
+            ))))
+        }
+    }
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let format = options
+        .archive_format
+        .or_else(|| ArchiveFormat::detect(content_type, response.url().as_str()))
+        .unwrap_or_default();
+    // A server that ignores the range, or whose archive changed, answers with all of it
+    let offset = match &resume {
+        Some((offset, _)) if response.status() == StatusCode::PARTIAL_CONTENT => {
+            if content_range_start(&response) != Some(*offset) {
+                remove_partial_download(partial.expect("only partial downloads resume"));
+                return Err(Box::new(ServerError(format!(
+                    "Downloading {} resumed at the wrong offset",
+                    archive_url
+                ))));
+            }
+            *offset
+        }
+        _ => 0,
+    };
+    let content_length = response.content_length().map(|len| len + offset);
+    let progress = if options.show_progress {
+        download_progress(content_length)
+    } else {
+        ProgressBar::hidden()
+    };
+    progress.set_position(offset);
+
+    // Zip's central directory sits at the end of the archive, so extraction can only start
+    // once the whole body has arrived. Keeping it in memory avoids a second copy on disk.
+    // Tarballs are buffered the same way so the cache is only replaced by a complete download.
+    let (mut archive, received) = if let Some(partial) = partial {
+        if let Some(parent) = partial.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if offset == 0 {
+            // Only a strong validator guarantees the bytes already here belong to the same file
+            let validator = etag
+                .clone()
+                .filter(|etag| !etag.starts_with("W/"))
+                .or_else(|| {
+                    response
+                        .headers()
+                        .get(reqwest::header::LAST_MODIFIED)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string)
+                });
+            match validator {
+                Some(validator) => fs::write(
+                    partial_info_path(partial),
+                    serde_json::to_string(&PartialDownload {
+                        url: archive_url.to_string(),
+                        validator,
+                    })?,
+                )?,
+                None => {
+                    let _ = fs::remove_file(partial_info_path(partial));
+                }
+            }
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(partial)?;
+        io::copy(&mut progress.wrap_read(&mut response), &mut file)?;
+        let received = file.metadata()?.len();
+        (ArchiveData::File(File::open(partial)?), received)
+    } else if options.spill_to_disk {
+        let mut file = tempfile::tempfile()?;
+        let received = io::copy(&mut progress.wrap_read(&mut response), &mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        (ArchiveData::File(file), received)
+    } else {
+        let capacity = content_length.and_then(|len| usize::try_from(len).ok());
+        let mut buffer = Vec::with_capacity(capacity.unwrap_or(0));
+        let received = io::copy(&mut progress.wrap_read(&mut response), &mut buffer)?;
+        (ArchiveData::Memory(buffer), received)
+    };
+    progress.finish_and_clear();
+
+    // A connection that drops cleanly can end the body early without any error
+    if let Some(expected) = content_length.filter(|&expected| expected != received) {
+        return Err(Box::new(ServerError(format!(
+            "Download of {} ended after {} of {} bytes",
+            archive_url, received, expected
+        ))));
+    }
+    if format == ArchiveFormat::Zip {
+        let readable = match &mut archive {
+            ArchiveData::Memory(data) => ZipArchive::new(io::Cursor::new(data.as_slice())).err(),
+            ArchiveData::File(file) => {
+                let result = ZipArchive::new(&mut *file).err();
+                file.seek(SeekFrom::Start(0))?;
+                result
+            }
+        };
+        if let Some(e) = readable {
+            // Retrying must start over rather than continue a file that can't be repaired
+            if let Some(partial) = partial {
+                remove_partial_download(partial);
+            }
+            return Err(Box::new(ServerError(format!(
+                "Downloaded archive {} is damaged: {}",
+                archive_url, e
+            ))));
+        }
+    }
+    Ok(Some(FetchedArchive {
+        archive,
+        format,
+        etag,
+    }))
+}
+
+/// Timeouts, failed connections, broken or incomplete bodies, and 5xx/429 responses are worth
+/// another attempt; missing repositories, auth failures, and local I/O errors such as a full
+/// disk are not.
+pub(crate) fn is_retryable(err: &(dyn error::Error + 'static)) -> bool {
+    if err.is::<ServerError>() {
+        return true;
+    }
+    // Reading the response body reports reqwest's errors wrapped in an `io::Error`
+    let network_error = match err.downcast_ref::<io::Error>() {
+        Some(err) => err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<reqwest::Error>()),
+        None => err.downcast_ref::<reqwest::Error>(),
+    };
+    network_error.is_some_and(|e| e.is_timeout() || e.is_connect() || e.is_body())
+}
+
+/// A byte progress bar when the server sent a `Content-Length`, otherwise a spinner.
+pub(crate) fn download_progress(content_length: Option<u64>) -> ProgressBar {
+    match content_length {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template(
+                "Downloading [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+            )
+            .expect("progress template is valid")
+            .progress_chars("=> "),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} Downloading {bytes} ({bytes_per_sec})")
+                .expect("progress template is valid"),
+        ),
+    }
+}
+
+/// Extracts an archive into `target_folder` and returns the directory to search.
+///
+/// Zip entries are written by `jobs` threads. With a single job the archive is streamed from
+/// `reader`; with more the whole archive is read into memory first. Tarballs are always
+/// extracted serially.
+pub fn extract_archive(
+    reader: impl Read + Seek,
+    format: ArchiveFormat,
+    target_folder: &Path,
+    jobs: usize,
+) -> Result<PathBuf, ContextError> {
+    let top_level = match format {
+        ArchiveFormat::Zip => extract_zip(reader, target_folder, jobs),
+        ArchiveFormat::TarGz => extract_tar_gz(reader, target_folder),
+    }
+    .map_err(ContextError::Extraction)?;
+    Ok(search_root(target_folder, top_level))
+}
+
+/// Extracts an archive held in memory like [`extract_archive`], sharing `data` between the
+/// threads writing zip entries instead of copying it.
+fn extract_archive_bytes(
+    data: &[u8],
+    format: ArchiveFormat,
+    target_folder: &Path,
+    jobs: usize,
+) -> Result<PathBuf, ContextError> {
+    if format != ArchiveFormat::Zip || jobs <= 1 {
+        return extract_archive(io::Cursor::new(data), format, target_folder, jobs);
+    }
+    let top_level =
+        extract_zip_parallel(data, target_folder, jobs).map_err(ContextError::Extraction)?;
+    Ok(search_root(target_folder, top_level))
+}
+
+/// The directory to search in `target_folder` after extracting `top_level`.
+fn search_root(target_folder: &Path, top_level: HashSet<PathBuf>) -> PathBuf {
+    // Archives from code hosts wrap everything in a `repo-branch/` directory; search inside it
+    if top_level.len() == 1 {
+        let root = target_folder.join(top_level.into_iter().next().unwrap());
+        if root.is_dir() {
+            return root;
+        }
+    }
+    target_folder.to_path_buf()
+}
+
+/// Extracts the archive file at `path` into `target_folder` and returns the directory to
+/// search, like [`extract_archive`]. Without a `format` it is guessed from the file name,
+/// e.g. `.tar.gz` or `.tgz`, falling back to zip.
+pub fn extract_archive_file(
+    path: &Path,
+    format: Option<ArchiveFormat>,
+    target_folder: &Path,
+    jobs: usize,
+) -> Result<PathBuf, ContextError> {
+    let format = format
+        .or_else(|| ArchiveFormat::detect(None, &path.to_string_lossy()))
+        .unwrap_or_default();
+    let file = File::open(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to open archive {}: {}", path.display(), e),
+        )
+    })?;
+    extract_archive(io::BufReader::new(file), format, target_folder, jobs)
+}
+
+/// Extracts a zip archive, returning the top-level entries it contained.
+pub(crate) fn extract_zip(
+    mut reader: impl Read + Seek,
+    target_folder: &Path,
+    jobs: usize,
+) -> Result<HashSet<PathBuf>, Box<dyn error::Error + Send + Sync>> {
+    if jobs <= 1 {
+        let mut archive = ZipArchive::new(reader)?;
+        let (top_level, files) = prepare_zip_entries(&mut archive, target_folder)?;
+        for (index, outpath) in &files {
+            write_zip_entry(&mut archive, *index, outpath)?;
+        }
+        return Ok(top_level);
+    }
+
+    reader.rewind()?;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    extract_zip_parallel(&data, target_folder, jobs)
+}
+
+/// Extracts a zip archive held in memory with `jobs` threads.
+fn extract_zip_parallel(
+    data: &[u8],
+    target_folder: &Path,
+    jobs: usize,
+) -> Result<HashSet<PathBuf>, Box<dyn error::Error + Send + Sync>> {
+    // `ZipArchive` hands out one entry at a time, so every worker decompresses through its
+    // own clone, which shares the parsed central directory and reads `data` directly
+    let mut archive = ZipArchive::new(io::Cursor::new(data))?;
+    let (top_level, files) = prepare_zip_entries(&mut archive, target_folder)?;
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    pool.install(|| {
+        files.par_iter().try_for_each_init(
+            || archive.clone(),
+            |archive, (index, outpath)| write_zip_entry(archive, *index, outpath),
+        )
+    })?;
+    Ok(top_level)
+}
+
+/// The top-level entries of a zip archive, and the index and output path of each file in it.
+type ZipEntries = (HashSet<PathBuf>, Vec<(usize, PathBuf)>);
+
+/// Checks every entry of a zip archive and creates its directories, returning the files that
+/// are still to be written.
+fn prepare_zip_entries<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    target_folder: &Path,
+) -> Result<ZipEntries, Box<dyn error::Error + Send + Sync>> {
+    // Create target directory if it doesn't exist
+    fs::create_dir_all(target_folder)?;
+    let canonical_target = fs::canonicalize(target_folder)?;
+    let long_target = long_path(target_folder);
+
+    let mut top_level = HashSet::new();
+    let mut files = Vec::new();
+    for i in 0..archive.len() {
+        // Only the metadata is needed, so nothing is decompressed yet
+        let file = archive.by_index_raw(i)?;
+        // Absolute paths and `..` that climb out of the archive are rejected, not rewritten
+        let Some(enclosed_name) = file.enclosed_name() else {
+            return Err(escaping_entry(file.name(), target_folder));
+        };
+        if let Some(first) = enclosed_name.components().next() {
+            top_level.insert(PathBuf::from(first.as_os_str()));
+        }
+        if skip_reserved_name(file.name(), &enclosed_name) {
+            continue;
+        }
+        let outpath = long_target.join(&enclosed_name);
+
+        if file.is_dir() {
+            fs::create_dir_all(&outpath)?;
+            ensure_within(&outpath, &canonical_target, file.name(), target_folder)?;
+        } else {
+            if let Some(p) = outpath.parent() {
+                fs::create_dir_all(p)?;
+                // A directory that already exists may be a symlink pointing elsewhere
+                ensure_within(p, &canonical_target, file.name(), target_folder)?;
+            }
+            files.push((i, outpath));
+        }
+    }
+    // A path listed twice ends up with its last entry, as when the entries are written in order
+    let mut seen = HashSet::new();
+    files.reverse();
+    files.retain(|(_, outpath)| seen.insert(outpath.clone()));
+    files.reverse();
+    Ok((top_level, files))
+}
+
+fn write_zip_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    outpath: &Path,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let mut file = archive.by_index(index)?;
+    let mut outfile = File::create(outpath)?;
+    io::copy(&mut file, &mut outfile)?;
+    Ok(())
+}
+
+/// Device names that Windows reserves in every directory, with or without an extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether Windows refuses `name` as a file or directory name: `con`, `aux.js`, `nul.tar.gz`,
+/// and the other device names, in any case. Only the part before the first dot counts, and
+/// trailing spaces are ignored.
+fn is_windows_reserved(name: &str) -> bool {
+    let stem = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(' ');
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// On Windows, warns about and reports an entry whose path contains a reserved name, which
+/// can't be created there. Such entries are skipped rather than failing the whole extraction.
+fn skip_reserved_name(entry_name: &str, path: &Path) -> bool {
+    if !cfg!(windows) {
+        return false;
+    }
+    let reserved = path.components().find_map(|component| match component {
+        Component::Normal(name) if is_windows_reserved(&name.to_string_lossy()) => Some(name),
+        _ => None,
+    });
+    let Some(reserved) = reserved else {
+        return false;
+    };
+    warn!(
+        event = "skipped", path = entry_name, reason = "reserved_name";
+        "Skipping {}: '{}' is a reserved file name on Windows",
+        entry_name,
+        reserved.to_string_lossy()
+    );
+    true
+}
+
+/// Turns `path` into an extended-length `\\?\` path, so entries nested deeper than the
+/// 260-character `MAX_PATH` limit (common under `node_modules`) can still be created.
+#[cfg(windows)]
+fn long_path(path: &Path) -> PathBuf {
+    use std::path::Prefix;
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let mut components = absolute.components();
+    let mut long = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) => PathBuf::from(format!(r"\\?\{}:\", letter as char)),
+            Prefix::UNC(server, share) => PathBuf::from(format!(
+                r"\\?\UNC\{}\{}\",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )),
+            // Already verbatim, or a device path
+            _ => return absolute,
+        },
+        _ => return absolute,
+    };
+    // Verbatim paths are used as written, so `/` separators must not be passed through
+    for component in components {
+        if let Component::Normal(name) = component {
+            long.push(name);
+        }
+    }
+    long
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Fails unless `path`, once symlinks are resolved, is inside `canonical_target`.
+pub(crate) fn ensure_within(
+    path: &Path,
+    canonical_target: &Path,
+    entry_name: &str,
+    target_folder: &Path,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    if fs::canonicalize(path)?.starts_with(canonical_target) {
+        Ok(())
+    } else {
+        Err(escaping_entry(entry_name, target_folder))
+    }
+}
+
+pub(crate) fn escaping_entry(
+    entry_name: &str,
+    target_folder: &Path,
+) -> Box<dyn error::Error + Send + Sync> {
+    format!(
+        "Archive entry '{}' would be extracted outside {}",
+        entry_name,
+        target_folder.display()
+    )
+    .into()
+}
+
+/// Extracts a gzipped tarball, returning the top-level entries it contained.
+pub(crate) fn extract_tar_gz(
+    reader: impl Read,
+    target_folder: &Path,
+) -> Result<HashSet<PathBuf>, Box<dyn error::Error + Send + Sync>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    fs::create_dir_all(target_folder)?;
+    let long_target = long_path(target_folder);
+
+    let mut top_level = HashSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        // GitHub tarballs start with a pax header carrying the commit id, not a file
+        if entry.header().entry_type().is_pax_global_extensions() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        if let Some(Component::Normal(first)) = path.components().next() {
+            top_level.insert(PathBuf::from(first));
+        }
+        if skip_reserved_name(&path.to_string_lossy(), &path) {
+            continue;
+        }
+        // `unpack_in` skips entries that would land outside `target_folder`; that means the
+        // archive is malicious, so stop rather than extract the rest of it
+        if !entry.unpack_in(&long_target)? {
+            let name = entry.path()?.display().to_string();
+            return Err(escaping_entry(&name, target_folder));
+        }
+    }
+    Ok(top_level)
+}
@@ -0,0 +1,1244 @@
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use ignore::WalkBuilder;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, error, info, warn};
+use rayon::prelude::*;
+use regex::bytes::Regex;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use crate::archives::{descend_archive, is_archive};
+use crate::content::{LineEnding, UTF8_BOM};
+use crate::error::ContextError;
+use crate::git::{annotate_commits, changed_files, CommitInfo};
+use crate::imports::follow_imports;
+use crate::language::LanguageOverrides;
+use crate::output::OutputFormat;
+use crate::transform::run_command;
+use crate::util::format_size;
+
+/// Extensionless build and configuration files added by `--common-config`. Real `.env` files
+/// are left out on purpose, since they usually hold secrets.
+pub const COMMON_CONFIG_FILES: &[&str] = &[
+    "Dockerfile",
+    "Containerfile",
+    "docker-compose.yml",
+    "docker-compose.yaml",
+    "compose.yaml",
+    ".dockerignore",
+    "Makefile",
+    "GNUmakefile",
+    "justfile",
+    "Justfile",
+    "Procfile",
+    "Jenkinsfile",
+    "Vagrantfile",
+    "Gemfile",
+    "Rakefile",
+    ".env.example",
+    ".env.sample",
+    ".gitignore",
+    ".gitattributes",
+    ".editorconfig",
+    ".tool-versions",
+    ".nvmrc",
+    ".python-version",
+    "rust-toolchain",
+];
+
+/// Named extension groups for `--type`, e.g. `--type code`.
+pub const TYPE_GROUPS: &[(&str, &[&str])] = &[
+    (
+        "code",
+        &[
+            "rs", "py", "js", "mjs", "cjs", "jsx", "ts", "tsx", "go", "java", "kt", "kts", "swift",
+            "c", "h", "cpp", "cc", "cxx", "hpp", "hh", "cs", "rb", "php", "scala", "dart", "ex",
+            "exs", "erl", "hs", "lua", "pl", "pm", "r", "sh", "bash", "zsh", "sql", "vue",
+            "svelte",
+        ],
+    ),
+    ("docs", &["md", "mdx", "txt", "rst", "adoc", "org"]),
+    (
+        "config",
+        &[
+            "toml",
+            "yaml",
+            "yml",
+            "json",
+            "ini",
+            "cfg",
+            "conf",
+            "properties",
+            "xml",
+        ],
+    ),
+    ("web", &["html", "htm", "css", "scss", "sass", "less"]),
+];
+
+/// Looks up a `--type` group by name, ignoring case.
+pub fn type_extensions(group: &str) -> Option<&'static [&'static str]> {
+    TYPE_GROUPS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(group))
+        .map(|(_, extensions)| *extensions)
+}
+
+/// Order in which matched files are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortOrder {
+    /// By path, alphabetically
+    #[default]
+    Path,
+    /// Largest first
+    Size,
+    /// Most recently modified first
+    Mtime,
+    /// By extension, then path
+    Ext,
+}
+
+/// Reorders `files` for `--sort`. The sort is stable, so ties stay in path order.
+pub(crate) fn sort_files(files: &mut [MatchedFile], order: SortOrder, reverse: bool) {
+    match order {
+        SortOrder::Path => files.sort_by(|a, b| a.display_path.cmp(&b.display_path)),
+        SortOrder::Size => files.sort_by_key(|file| Reverse(file.size)),
+        SortOrder::Mtime => files.sort_by_cached_key(|file| {
+            let modified = fs::metadata(&file.path).and_then(|metadata| metadata.modified());
+            Reverse(modified.ok())
+        }),
+        SortOrder::Ext => files.sort_by_cached_key(|file| {
+            let extension = file
+                .display_path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            (extension, file.display_path.clone())
+        }),
+    }
+    if reverse {
+        files.reverse();
+    }
+}
+
+/// Options controlling which files are matched and how their contents are printed.
+#[derive(Debug, Default, Clone)]
+pub struct SearchOptions {
+    pub extensions: Vec<String>,
+    /// Exact file names to include regardless of extension, e.g. `Dockerfile`
+    pub filenames: Vec<String>,
+    pub case_sensitive: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub ignored_dirs: Vec<String>,
+    /// When non-empty, only these top-level directories are searched
+    pub only_dirs: Vec<String>,
+    pub ignored_files: Vec<String>,
+    pub respect_gitignore: bool,
+    pub follow_symlinks: bool,
+    /// Print a one-line stub naming each directory pruned by `ignored_dirs` and its file count
+    pub stub_ignored: bool,
+    /// Search inside `.zip` and `.jar` files found during the walk
+    pub descend_archives: bool,
+    pub max_depth: Option<usize>,
+    /// Add the local files that matched Rust and Python files import, transitively
+    pub follow_imports: bool,
+    /// Import hops followed from a matched file; `None` follows the whole closure
+    pub max_import_depth: Option<usize>,
+    pub since: Option<Duration>,
+    pub max_file_size: Option<u64>,
+    pub sort: SortOrder,
+    /// Print files in the opposite of the `sort` order
+    pub reverse: bool,
+    pub content_match: Option<Regex>,
+    pub content_exclude: Option<Regex>,
+    /// Shell command that receives the matched paths on stdin and prints the ones to keep
+    pub filter_command: Option<String>,
+    /// Keep only files with uncommitted changes (modified, staged, or untracked) in git
+    pub only_changed: bool,
+    pub print_contents: bool,
+    pub include_binary: bool,
+    pub strict_utf8: bool,
+    pub tree: bool,
+    /// Name files by their base name alone, numbering any that collide
+    pub flatten: bool,
+    /// Show the last commit's hash, author, and date in each file header
+    pub with_git_info: bool,
+    /// Show each file's size, and its tokens when they are counted, in its header
+    pub annotate_headers: bool,
+    pub overview: bool,
+    /// Start the output with a fenced JSON summary of the files, sizes, and estimated tokens
+    pub info_header: bool,
+    pub dry_run: bool,
+    pub interactive: bool,
+    pub dedupe: bool,
+    pub exclude_empty: bool,
+    pub manifest_hash: bool,
+    /// Make the build fail, after every file has been tried, when any matched file can't be read
+    pub fail_on_read_error: bool,
+    /// Lowercase extension (without the dot) to a built-in transform or shell command applied
+    /// to the contents of matching files, see [`apply_transform`](crate::apply_transform)
+    pub transforms: BTreeMap<String, String>,
+    pub redact_secrets: bool,
+    /// Remove comments and docstrings from printed contents
+    pub strip_comments: bool,
+    /// Print only declarations, with function bodies elided, see [`outline`](crate::outline)
+    pub outline: bool,
+    /// Rewrite line endings in printed contents; `None` keeps them as they are
+    pub normalize_eol: Option<LineEnding>,
+    pub line_numbers: bool,
+    pub max_lines_per_file: Option<usize>,
+    /// Print at most this many bytes of each file, for quick previews
+    pub head: Option<u64>,
+    /// Read files of at least this many bytes through a memory map instead of a buffer
+    pub mmap_threshold: Option<u64>,
+    pub count_tokens: bool,
+    /// Directory caching token counts by content hash; `None` counts every file afresh
+    pub token_cache: Option<PathBuf>,
+    pub max_tokens: Option<usize>,
+    /// Files rendered ahead of the writer, bounding memory (64 when not set)
+    pub buffer_size: Option<usize>,
+    pub format: OutputFormat,
+    pub header_template: Option<String>,
+    pub footer_template: Option<String>,
+    /// Color plain-format headers and separators (for terminal output only)
+    pub color: bool,
+    pub progress: bool,
+}
+
+pub(crate) fn build_glob(pattern: &str, case_insensitive: bool) -> Result<Glob, ContextError> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|source| invalid_glob(pattern, source))
+}
+
+fn invalid_glob(pattern: &str, source: globset::Error) -> ContextError {
+    ContextError::InvalidGlob {
+        pattern: pattern.to_string(),
+        source,
+    }
+}
+
+/// Combines already checked globs, so a failure can only concern the whole set.
+fn build_set(builder: GlobSetBuilder, patterns: &[String]) -> Result<GlobSet, ContextError> {
+    builder
+        .build()
+        .map_err(|source| invalid_glob(&patterns.join(" "), source))
+}
+
+pub(crate) fn build_globset(patterns: &[String]) -> Result<GlobSet, ContextError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(build_glob(pattern, false)?);
+    }
+    build_set(builder, patterns)
+}
+
+/// Why a file was left out of the output. Every skip is logged with `event = "skipped"` and
+/// the reason's [`code`](SkipReason::code); the message comes from its `Display`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Matched none of `--extensions`, `--filenames`, or `--include`
+    NotIncluded,
+    /// Matched this `--exclude` glob
+    Excluded(String),
+    /// A directory named in `--ignored-dirs`, skipped with everything in it
+    IgnoredDir,
+    /// A top-level file, or a top-level directory not named in `--only-dirs`
+    OutsideOnlyDirs,
+    /// Matched this `--ignored-files` pattern
+    IgnoredFile(String),
+    /// Matched this `.llmignore` pattern
+    Llmignore(String),
+    /// Excluded by a gitignore file under `--respect-gitignore`
+    Gitignored,
+    /// Not modified within `--since`
+    NotRecent,
+    /// Larger than `--max-file-size`
+    TooLarge { size: u64, max_size: u64 },
+    /// Looks binary and `--include-binary` isn't set
+    Binary,
+    /// Empty or whitespace-only under `--exclude-empty`
+    Empty,
+    /// Already printed through an overlapping search path
+    Duplicate,
+    /// Left out of the list printed by `--filter-command`
+    FilterCommand,
+    /// Has no uncommitted changes under `--only-changed`
+    Unchanged,
+    /// Contents don't match `--content-match`
+    ContentMismatch,
+    /// Contents match `--content-exclude`
+    ContentExcluded,
+}
+
+impl SkipReason {
+    /// Short machine-readable name, used as the `reason` of the log event.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SkipReason::NotIncluded => "not_included",
+            SkipReason::Excluded(_) => "excluded",
+            SkipReason::IgnoredDir => "ignored_dir",
+            SkipReason::OutsideOnlyDirs => "outside_only_dirs",
+            SkipReason::IgnoredFile(_) => "ignored_file",
+            SkipReason::Llmignore(_) => "llmignore",
+            SkipReason::Gitignored => "gitignored",
+            SkipReason::NotRecent => "not_recent",
+            SkipReason::TooLarge { .. } => "too_large",
+            SkipReason::Binary => "binary",
+            SkipReason::Empty => "empty",
+            SkipReason::Duplicate => "duplicate",
+            SkipReason::FilterCommand => "filter_command",
+            SkipReason::Unchanged => "unchanged",
+            SkipReason::ContentMismatch => "content_match",
+            SkipReason::ContentExcluded => "content_exclude",
+        }
+    }
+
+    /// Skips the user is likely to be surprised by are warnings; filters doing what they
+    /// were asked only show up at `-vv` or with `--verbose-skip`.
+    fn level(&self) -> log::Level {
+        match self {
+            SkipReason::TooLarge { .. } | SkipReason::Binary => log::Level::Warn,
+            SkipReason::Empty | SkipReason::Duplicate => log::Level::Info,
+            _ => log::Level::Debug,
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NotIncluded => {
+                write!(f, "matches none of --extensions, --filenames, or --include")
+            }
+            SkipReason::Excluded(pattern) => write!(f, "matches --exclude {}", pattern),
+            SkipReason::IgnoredDir => write!(f, "directory is in --ignored-dirs"),
+            SkipReason::OutsideOnlyDirs => write!(f, "not inside one of --only-dirs"),
+            SkipReason::IgnoredFile(pattern) => write!(f, "matches --ignored-files {}", pattern),
+            SkipReason::Llmignore(pattern) => {
+                write!(f, "matches {} pattern {}", LLMIGNORE_FILE_NAME, pattern)
+            }
+            SkipReason::Gitignored => write!(f, "ignored by .gitignore"),
+            SkipReason::NotRecent => write!(f, "not modified within --since"),
+            SkipReason::TooLarge { size, max_size } => write!(
+                f,
+                "{} is larger than --max-file-size {}",
+                format_size(*size),
+                format_size(*max_size)
+            ),
+            SkipReason::Binary => write!(f, "binary file"),
+            SkipReason::Empty => write!(f, "empty file"),
+            SkipReason::Duplicate => write!(f, "already included through another search path"),
+            SkipReason::FilterCommand => write!(f, "not kept by --filter-command"),
+            SkipReason::Unchanged => write!(f, "no uncommitted changes (--only-changed)"),
+            SkipReason::ContentMismatch => write!(f, "contents don't match --content-match"),
+            SkipReason::ContentExcluded => write!(f, "contents match --content-exclude"),
+        }
+    }
+}
+
+/// Logs a skipped file as a `skipped` event at the level its reason calls for.
+pub(crate) fn log_skip(display_path: &Path, reason: &SkipReason) {
+    log::log!(
+        reason.level(),
+        event = "skipped", path:% = display_path.display(), reason = reason.code();
+        "Skipping {}: {}", display_path.display(), reason
+    );
+}
+
+/// Decides which candidate files are emitted, based on `--include`/`--exclude`/`--ignored-files` globs.
+/// `--extensions` are folded into the include set as `**/*.{ext}` globs.
+pub(crate) struct FileMatcher {
+    pub(crate) include: Option<GlobSet>,
+    pub(crate) exclude: GlobSet,
+    exclude_patterns: Vec<String>,
+    /// `--ignored-files`, which also match against just the file name
+    pub(crate) ignored_files: GlobSet,
+    ignored_file_patterns: Vec<String>,
+    /// Patterns from a `.llmignore` file in the search root, using gitignore syntax
+    pub(crate) llmignore: Option<Gitignore>,
+}
+
+pub(crate) const LLMIGNORE_FILE_NAME: &str = ".llmignore";
+
+pub(crate) fn load_llmignore(directory: &Path) -> Result<Option<Gitignore>, ContextError> {
+    let path = directory.join(LLMIGNORE_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(directory);
+    let invalid = |source| ContextError::InvalidIgnoreFile {
+        path: path.clone(),
+        source,
+    };
+    if let Some(e) = builder.add(&path) {
+        return Err(invalid(e));
+    }
+    let gitignore = builder.build().map_err(invalid)?;
+    info!("Loaded ignore patterns from {}", path.display());
+    Ok(Some(gitignore))
+}
+
+impl FileMatcher {
+    pub(crate) fn new(directory: &Path, options: &SearchOptions) -> Result<Self, ContextError> {
+        let include = if options.extensions.is_empty()
+            && options.filenames.is_empty()
+            && options.include.is_empty()
+        {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for ext in &options.extensions {
+                // `.PNG` from a Windows checkout should match `--extensions png`
+                let pattern = format!("**/*.{}", globset::escape(ext.trim_start_matches('.')));
+                builder.add(build_glob(&pattern, !options.case_sensitive)?);
+            }
+            for name in &options.filenames {
+                let pattern = format!("**/{}", globset::escape(name));
+                builder.add(build_glob(&pattern, false)?);
+            }
+            for pattern in &options.include {
+                builder.add(build_glob(pattern, false)?);
+            }
+            Some(build_set(builder, &options.include)?)
+        };
+        Ok(FileMatcher {
+            include,
+            exclude: build_globset(&options.exclude)?,
+            exclude_patterns: options.exclude.clone(),
+            ignored_files: build_globset(&options.ignored_files)?,
+            ignored_file_patterns: options.ignored_files.clone(),
+            llmignore: load_llmignore(directory)?,
+        })
+    }
+
+    /// Applies the name filters to a path: the exclusions first, then the include filters.
+    pub(crate) fn check(&self, relative_path: &Path) -> Result<(), SkipReason> {
+        self.check_excluded(relative_path)?;
+        if self
+            .include
+            .as_ref()
+            .is_some_and(|include| !include.is_match(relative_path))
+        {
+            return Err(SkipReason::NotIncluded);
+        }
+        Ok(())
+    }
+
+    /// Whether an exclude glob, `--ignored-files`, or `.llmignore` rules out the path,
+    /// regardless of the include filters.
+    pub(crate) fn is_excluded(&self, relative_path: &Path) -> bool {
+        self.check_excluded(relative_path).is_err()
+    }
+
+    fn check_excluded(&self, relative_path: &Path) -> Result<(), SkipReason> {
+        if let Some(&index) = self.exclude.matches(relative_path).first() {
+            return Err(SkipReason::Excluded(self.exclude_patterns[index].clone()));
+        }
+        let ignored_file = self.ignored_files.matches(relative_path);
+        let ignored_file = match ignored_file.first() {
+            Some(&index) => Some(index),
+            None => relative_path
+                .file_name()
+                .and_then(|name| self.ignored_files.matches(name).first().copied()),
+        };
+        if let Some(index) = ignored_file {
+            return Err(SkipReason::IgnoredFile(
+                self.ignored_file_patterns[index].clone(),
+            ));
+        }
+        if let Some(llmignore) = &self.llmignore {
+            if let Match::Ignore(glob) = llmignore.matched_path_or_any_parents(relative_path, false)
+            {
+                return Err(SkipReason::Llmignore(glob.original().to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a file modified at `metadata`'s mtime falls within `--since`. Files with no
+/// readable mtime are kept rather than silently dropped.
+fn is_recent(metadata: &fs::Metadata, options: &SearchOptions) -> bool {
+    options.since.is_none_or(|since| {
+        metadata
+            .modified()
+            .map(|modified| modified.elapsed().map_or(true, |age| age <= since))
+            .unwrap_or(true)
+    })
+}
+
+/// Every check a walked file has to pass before it is matched, in one place so the reason
+/// reported for a skip is always the same: name filters, then `--since`, then
+/// `--max-file-size` (checked from metadata so oversized files are never read into memory).
+pub(crate) fn should_include(
+    relative_path: &Path,
+    metadata: &fs::Metadata,
+    matcher: &FileMatcher,
+    options: &SearchOptions,
+) -> Result<(), SkipReason> {
+    matcher.check(relative_path)?;
+    if !is_recent(metadata, options) {
+        return Err(SkipReason::NotRecent);
+    }
+    if let Some(max_size) = options.max_file_size {
+        if metadata.len() > max_size {
+            return Err(SkipReason::TooLarge {
+                size: metadata.len(),
+                max_size,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Applies `--ignored-dirs` to a single walk entry. Files are filtered later by
+/// [`FileMatcher`], since `--ignored-files` globs may need the relative path.
+pub(crate) fn keep_entry(file_name: &OsStr, is_dir: bool, ignored_dirs: &[String]) -> bool {
+    !is_dir || !ignored_dirs.contains(&file_name.to_string_lossy().to_string())
+}
+
+/// Applies `--only-dirs` to a walk entry `depth` levels below the search root: at the top
+/// level only the named directories are kept, and whatever is inside them is left to the
+/// other filters.
+fn in_only_dirs(depth: usize, file_name: &OsStr, is_dir: bool, only_dirs: &[String]) -> bool {
+    let keep = allowed_by_only_dirs(depth, file_name, is_dir, only_dirs);
+    if !keep {
+        log_skip(Path::new(file_name), &SkipReason::OutsideOnlyDirs);
+    }
+    keep
+}
+
+fn allowed_by_only_dirs(
+    depth: usize,
+    file_name: &OsStr,
+    is_dir: bool,
+    only_dirs: &[String],
+) -> bool {
+    if only_dirs.is_empty() || depth != 1 {
+        return true;
+    }
+    let name = file_name.to_string_lossy();
+    is_dir
+        && only_dirs
+            .iter()
+            .any(|dir| dir.trim_end_matches('/') == name)
+}
+
+/// Walks `directory` and returns every file that survives the ignore rules, sorted by path
+/// so that repeated runs (and token budgets) always see the same order. With
+/// `--stub-ignored`, the directories pruned by `--ignored-dirs` are added to `ignored`.
+pub(crate) fn collect_candidates(
+    directory: &Path,
+    options: &SearchOptions,
+    ignored: &mut Vec<PathBuf>,
+) -> Result<Vec<PathBuf>, ContextError> {
+    let mut files = Vec::new();
+    let follow_symlinks = options.follow_symlinks;
+    let visited = Arc::new(Mutex::new(HashSet::new()));
+    let pruned = Arc::new(Mutex::new(Vec::new()));
+    // Walkers count the root itself as depth 0, but `--max-depth 0` means the files in it
+    let max_depth = options.max_depth.map(|depth| depth.saturating_add(1));
+
+    if options.respect_gitignore {
+        let ignored_dirs = options.ignored_dirs.clone();
+        let only_dirs = options.only_dirs.clone();
+        let visited = Arc::clone(&visited);
+        let pruned = Arc::clone(&pruned);
+        let walker = WalkBuilder::new(directory)
+            .hidden(false)
+            .ignore(false)
+            .require_git(false)
+            .follow_links(follow_symlinks)
+            .max_depth(max_depth)
+            .filter_entry(move |e| {
+                let is_dir = e.file_type().is_some_and(|t| t.is_dir());
+                // Without the hidden-file filter the .git directory itself would be walked
+                !(is_dir && e.file_name() == ".git")
+                    && keep_or_record(e.path(), is_dir, &ignored_dirs, &pruned)
+                    && in_only_dirs(e.depth(), e.file_name(), is_dir, &only_dirs)
+                    && !(follow_symlinks && is_dir && !first_visit(&visited, e.path()))
+            })
+            .build();
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if follow_symlinks && is_walk_loop(&e) => {
+                    warn!(event = "skipped", reason = "symlink_cycle"; "Skipping symlink cycle: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                files.push(entry.into_path());
+            }
+        }
+        if log::log_enabled!(log::Level::Debug) {
+            log_gitignored(directory, options, max_depth, &files);
+        }
+    } else {
+        let mut walker = WalkDir::new(directory).follow_links(follow_symlinks);
+        if let Some(max_depth) = max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+        let walker = walker.into_iter().filter_entry(|e| {
+            let is_dir = e.file_type().is_dir();
+            keep_or_record(e.path(), is_dir, &options.ignored_dirs, &pruned)
+                && in_only_dirs(e.depth(), e.file_name(), is_dir, &options.only_dirs)
+                && !(follow_symlinks && is_dir && !first_visit(&visited, e.path()))
+        });
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if follow_symlinks && e.loop_ancestor().is_some() => {
+                    warn!(event = "skipped", reason = "symlink_cycle"; "Skipping symlink cycle: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(io::Error::from(e).into()),
+            };
+            if entry.file_type().is_file() {
+                files.push(entry.into_path());
+            }
+        }
+    }
+
+    files.sort();
+    let mut pruned = std::mem::take(&mut *pruned.lock().unwrap());
+    pruned.sort();
+    for dir in &pruned {
+        log_skip(
+            dir.strip_prefix(directory).unwrap_or(dir),
+            &SkipReason::IgnoredDir,
+        );
+    }
+    if options.stub_ignored {
+        ignored.extend(pruned);
+    }
+    Ok(files)
+}
+
+/// Reports the files that a `--respect-gitignore` walk left out, which the walker drops
+/// without a word, by walking again without the gitignore rules. Only done when skips are
+/// being logged.
+fn log_gitignored(
+    directory: &Path,
+    options: &SearchOptions,
+    max_depth: Option<usize>,
+    kept: &[PathBuf],
+) {
+    let kept: HashSet<&Path> = kept.iter().map(PathBuf::as_path).collect();
+    let mut walker = WalkDir::new(directory);
+    if let Some(max_depth) = max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+    let walker = walker.into_iter().filter_entry(|e| {
+        let is_dir = e.file_type().is_dir();
+        !(is_dir && e.file_name() == ".git")
+            && keep_entry(e.file_name(), is_dir, &options.ignored_dirs)
+            && allowed_by_only_dirs(e.depth(), e.file_name(), is_dir, &options.only_dirs)
+    });
+    for entry in walker.filter_map(Result::ok) {
+        let path = entry.path();
+        if entry.file_type().is_file() && !kept.contains(path) {
+            let relative_path = path.strip_prefix(directory).unwrap_or(path);
+            log_skip(relative_path, &SkipReason::Gitignored);
+        }
+    }
+}
+
+/// [`keep_entry`], remembering the directories it prunes.
+fn keep_or_record(
+    path: &Path,
+    is_dir: bool,
+    ignored_dirs: &[String],
+    pruned: &Mutex<Vec<PathBuf>>,
+) -> bool {
+    let keep = keep_entry(path.file_name().unwrap_or_default(), is_dir, ignored_dirs);
+    if !keep {
+        pruned.lock().unwrap().push(path.to_path_buf());
+    }
+    keep
+}
+
+/// Counts the files under each of `dirs` for `--stub-ignored`, naming the directories
+/// relative to `root`.
+fn count_ignored(dirs: Vec<PathBuf>, root: &Path) -> Vec<(PathBuf, usize)> {
+    dirs.into_par_iter()
+        .map(|dir| {
+            let files = WalkDir::new(&dir)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .count();
+            (dir.strip_prefix(root).unwrap_or(&dir).to_path_buf(), files)
+        })
+        .collect()
+}
+
+/// Records the canonical form of a directory reached while following symlinks, returning
+/// false (and warning) if it has already been walked, which is how cycles are broken.
+pub(crate) fn first_visit(visited: &Mutex<HashSet<PathBuf>>, path: &Path) -> bool {
+    let Ok(canonical) = fs::canonicalize(path) else {
+        return true;
+    };
+    let mut visited = visited.lock().unwrap();
+    if visited.insert(canonical) {
+        true
+    } else {
+        warn!(
+            "Skipping {}: directory already visited through another symlink",
+            path.display()
+        );
+        false
+    }
+}
+
+pub(crate) fn is_walk_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_walk_loop(err),
+        _ => false,
+    }
+}
+
+/// A file that passed every filter, located both on disk and relative to the repository root.
+#[derive(Debug, Clone)]
+pub struct MatchedFile {
+    pub path: PathBuf,
+    pub display_path: PathBuf,
+    pub size: u64,
+    /// Language set by a `linguist-language` attribute in the root's `.gitattributes`
+    pub language: Option<String>,
+    /// For a file found inside an archive, the temporary directory it was extracted to
+    pub extraction: Option<Arc<TempDir>>,
+    /// Last commit to touch the file, looked up for `--with-git-info`
+    pub commit: Option<CommitInfo>,
+}
+
+/// Walks `directory` and returns the files selected by the globs and size limit, sorted by path.
+/// With `--stub-ignored`, the pruned `--ignored-dirs` and their file counts go to `ignored`.
+pub(crate) fn match_files(
+    directory: &Path,
+    root: &Path,
+    options: &SearchOptions,
+    read_errors: &mut Vec<PathBuf>,
+    ignored: &mut Vec<(PathBuf, usize)>,
+) -> Result<Vec<MatchedFile>, ContextError> {
+    let matcher = FileMatcher::new(directory, options)?;
+    let language_overrides = LanguageOverrides::load(root);
+    let mut matched = Vec::new();
+
+    let progress = if options.progress {
+        ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} Scanned {pos} files, {msg} matched")
+                .expect("progress template is valid"),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+    progress.set_message("0");
+    let mut pruned = Vec::new();
+    let candidates = collect_candidates(directory, options, &mut pruned)?;
+    // Imported files only need to survive the ignore rules, not the extension or include
+    // filters, so an entry point can pull in whatever it depends on
+    let walked: HashSet<PathBuf> = if options.follow_imports {
+        candidates.iter().cloned().collect()
+    } else {
+        HashSet::new()
+    };
+    for file_path in candidates {
+        progress.inc(1);
+        let relative_path = file_path.strip_prefix(directory).unwrap_or(&file_path);
+        // Headers name files relative to the repository root rather than the temp/cwd path
+        let display_path = file_path
+            .strip_prefix(root)
+            .unwrap_or(&file_path)
+            .to_path_buf();
+        let filtered = matcher.check(relative_path);
+        // An archive can hold matching files even when it doesn't match itself
+        let descend = options.descend_archives
+            && is_archive(&file_path)
+            && matches!(filtered, Ok(()) | Err(SkipReason::NotIncluded));
+        if let Err(reason) = &filtered {
+            if !descend {
+                log_skip(&display_path, reason);
+                continue;
+            }
+        }
+
+        let metadata = match fs::metadata(&file_path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                error!(
+                    event = "skipped", path:% = display_path.display(), reason = "read_error";
+                    "Error reading metadata for {}: {}", file_path.display(), e
+                );
+                read_errors.push(display_path);
+                continue;
+            }
+        };
+        let size = metadata.len();
+        let included = should_include(relative_path, &metadata, &matcher, options);
+        if descend && is_recent(&metadata, options) {
+            match descend_archive(&file_path, &display_path, &matcher, options) {
+                Ok(entries) => matched.extend(entries),
+                Err(e) => warn!("Could not search {}: {}", display_path.display(), e),
+            }
+        }
+        if let Err(reason) = included {
+            log_skip(&display_path, &reason);
+            continue;
+        }
+        let language = language_overrides
+            .as_ref()
+            .and_then(|overrides| overrides.language_for(&display_path));
+        matched.push(MatchedFile {
+            path: file_path,
+            display_path,
+            size,
+            language,
+            extraction: None,
+            commit: None,
+        });
+        progress.set_message(matched.len().to_string());
+    }
+    progress.finish_and_clear();
+
+    if options.content_match.is_some() || options.content_exclude.is_some() {
+        let unreadable = Mutex::new(Vec::new());
+        matched = matched
+            .into_par_iter()
+            .filter(|file| match matches_content(file, options) {
+                Ok(matches) => matches,
+                Err(_) => {
+                    unreadable.lock().unwrap().push(file.display_path.clone());
+                    false
+                }
+            })
+            .collect();
+        read_errors.extend(unreadable.into_inner().unwrap());
+    }
+
+    if options.follow_imports {
+        let entries: Vec<PathBuf> = matched
+            .iter()
+            .filter(|file| file.extraction.is_none())
+            .map(|file| file.path.clone())
+            .collect();
+        for file_path in follow_imports(&entries, directory, options.max_import_depth) {
+            let relative_path = file_path.strip_prefix(directory).unwrap_or(&file_path);
+            if !walked.contains(&file_path) || matcher.is_excluded(relative_path) {
+                continue;
+            }
+            let display_path = file_path
+                .strip_prefix(root)
+                .unwrap_or(&file_path)
+                .to_path_buf();
+            let Ok(metadata) = fs::metadata(&file_path) else {
+                read_errors.push(display_path);
+                continue;
+            };
+            if options
+                .max_file_size
+                .is_some_and(|max_size| metadata.len() > max_size)
+            {
+                continue;
+            }
+            debug!("Following import of {}", display_path.display());
+            let language = language_overrides
+                .as_ref()
+                .and_then(|overrides| overrides.language_for(&display_path));
+            matched.push(MatchedFile {
+                path: file_path,
+                display_path,
+                size: metadata.len(),
+                language,
+                extraction: None,
+                commit: None,
+            });
+        }
+        matched.sort_by(|a, b| a.display_path.cmp(&b.display_path));
+    }
+
+    for file in &matched {
+        info!("Found file: {}", file.display_path.display());
+    }
+    ignored.extend(count_ignored(pruned, root));
+    Ok(matched)
+}
+
+/// Applies `--content-match` and `--content-exclude` to the raw bytes of a file. Read errors
+/// are logged before they are returned.
+pub(crate) fn matches_content(file: &MatchedFile, options: &SearchOptions) -> io::Result<bool> {
+    let contents = match fs::read(&file.path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!(
+                event = "skipped", path:% = file.display_path.display(), reason = "read_error";
+                "Error reading file {}: {}", file.path.display(), e
+            );
+            return Err(e);
+        }
+    };
+    let reason = if !options
+        .content_match
+        .as_ref()
+        .is_none_or(|regex| regex.is_match(&contents))
+    {
+        SkipReason::ContentMismatch
+    } else if options
+        .content_exclude
+        .as_ref()
+        .is_some_and(|regex| regex.is_match(&contents))
+    {
+        SkipReason::ContentExcluded
+    } else {
+        return Ok(true);
+    };
+    log_skip(&file.display_path, &reason);
+    Ok(false)
+}
+
+/// Lets the user untick matched files in a checklist; everything starts selected.
+pub(crate) fn select_files(matched: Vec<MatchedFile>) -> Result<Vec<MatchedFile>, ContextError> {
+    if matched.is_empty() {
+        return Ok(matched);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(ContextError::NoTerminal);
+    }
+    let items: Vec<String> = matched
+        .iter()
+        .map(|file| file.display_path.display().to_string())
+        .collect();
+    let selected = dialoguer::MultiSelect::new()
+        .with_prompt("Select files to include (space toggles, enter confirms)")
+        .items(&items)
+        .defaults(&vec![true; items.len()])
+        .interact()
+        .map_err(|dialoguer::Error::IO(e)| e)?;
+    let selected: HashSet<usize> = selected.into_iter().collect();
+    Ok(matched
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selected.contains(i))
+        .map(|(_, file)| file)
+        .collect())
+}
+
+/// Totals for the `--stats` report, accumulated across every searched root.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub files: usize,
+    pub bytes: u64,
+    /// Tokens actually printed, when they were counted
+    pub tokens: usize,
+    /// Files written to the `--bundle` archive
+    pub bundled: usize,
+    /// Files copied into the `--split-output` directory
+    pub split: usize,
+    /// Display paths of matched files that could not be read, for `--fail-on-read-error`
+    pub read_errors: Vec<PathBuf>,
+    /// Byte range of each printed file in the document, for [`split_parts`](crate::split_parts)
+    pub file_spans: Vec<Range<usize>>,
+    /// Files left out by `--exclude-empty`
+    pub skipped_empty: usize,
+    /// Combined hash of the included files' names and contents, for `--print-manifest-hash`
+    pub manifest_hash: Option<blake3::Hash>,
+    /// Extension (without the dot) to file count and bytes
+    pub by_extension: BTreeMap<String, (usize, u64)>,
+    /// The largest files seen so far, biggest first
+    pub largest: Vec<(PathBuf, u64)>,
+    /// With `--stub-ignored`, the directories pruned by `--ignored-dirs`, named relative to
+    /// their root, with the number of files in each
+    pub ignored_dirs: Vec<(PathBuf, usize)>,
+}
+
+impl Stats {
+    const LARGEST_COUNT: usize = 5;
+
+    pub fn record(&mut self, file: &MatchedFile) {
+        self.files += 1;
+        self.bytes += file.size;
+        let extension = file
+            .path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "(none)".to_string());
+        let entry = self.by_extension.entry(extension).or_default();
+        entry.0 += 1;
+        entry.1 += file.size;
+
+        self.largest.push((file.display_path.clone(), file.size));
+        self.largest.sort_by_key(|(_, size)| Reverse(*size));
+        self.largest.truncate(Self::LARGEST_COUNT);
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "Matched {} files, {}",
+            self.files,
+            format_size(self.bytes)
+        )?;
+        if self.skipped_empty > 0 {
+            writeln!(out, "Skipped {} empty files", self.skipped_empty)?;
+        }
+        if self.files == 0 {
+            return Ok(());
+        }
+
+        let mut extensions: Vec<_> = self.by_extension.iter().collect();
+        extensions.sort_by_key(|(_, (_, bytes))| Reverse(*bytes));
+        writeln!(out, "By extension:")?;
+        for (extension, (count, bytes)) in extensions {
+            let share = if self.bytes == 0 {
+                0.0
+            } else {
+                *bytes as f64 * 100.0 / self.bytes as f64
+            };
+            writeln!(
+                out,
+                "  {:<10} {:>6} files {:>8} {:>5.1}%",
+                extension,
+                count,
+                format_size(*bytes),
+                share
+            )?;
+        }
+
+        writeln!(out, "Largest files:")?;
+        for (path, size) in &self.largest {
+            writeln!(out, "  {:>8}  {}", format_size(*size), path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// True for zero-byte files and files holding nothing but whitespace (or a lone BOM).
+pub(crate) fn is_empty_file(file: &MatchedFile) -> bool {
+    if file.size == 0 {
+        return true;
+    }
+    match fs::read(&file.path) {
+        Ok(contents) => contents
+            .strip_prefix(UTF8_BOM.as_bytes())
+            .unwrap_or(&contents)
+            .iter()
+            .all(u8::is_ascii_whitespace),
+        Err(e) => {
+            error!("Error reading file {}: {}", file.path.display(), e);
+            false
+        }
+    }
+}
+
+/// Sends the display paths of `files` to `command` on stdin, one per line, and keeps the files
+/// whose paths it prints back, in their original order. A failing command fails the search.
+pub(crate) fn filter_with_command(
+    files: Vec<MatchedFile>,
+    command: &str,
+) -> Result<Vec<MatchedFile>, ContextError> {
+    let mut list = String::new();
+    for file in &files {
+        list.push_str(&file.display_path.display().to_string());
+        list.push('\n');
+    }
+    let output = run_command(command, &list)?;
+    let mut keep: HashSet<&str> = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let files: Vec<MatchedFile> = files
+        .into_iter()
+        .filter(|file| {
+            let kept = keep.remove(file.display_path.display().to_string().as_str());
+            if !kept {
+                log_skip(&file.display_path, &SkipReason::FilterCommand);
+            }
+            kept
+        })
+        .collect();
+    for path in keep {
+        warn!(
+            "--filter-command printed {}, which is not a candidate file",
+            path
+        );
+    }
+    Ok(files)
+}
+
+/// Drops empty files for `--exclude-empty`, counting them in `stats`.
+pub(crate) fn skip_empty(files: Vec<MatchedFile>, stats: &mut Stats) -> Vec<MatchedFile> {
+    let (empty, files): (Vec<_>, Vec<_>) = files.into_par_iter().partition(is_empty_file);
+    for file in &empty {
+        log_skip(&file.display_path, &SkipReason::Empty);
+    }
+    stats.skipped_empty += empty.len();
+    files
+}
+
+/// Searches `directory` and returns the files to print, labelled relative to `root`, in
+/// order. They are recorded in `stats`, and with `--stub-ignored` so are the pruned
+/// directories to mention before them.
+///
+/// Files already recorded in `seen` (by canonical path) are skipped so overlapping search
+/// paths don't print anything twice.
+pub fn find_files(
+    directory: &Path,
+    root: &Path,
+    options: &SearchOptions,
+    seen: &mut HashSet<PathBuf>,
+    stats: &mut Stats,
+) -> Result<Vec<MatchedFile>, ContextError> {
+    let mut matched = match_files(
+        directory,
+        root,
+        options,
+        &mut stats.read_errors,
+        &mut stats.ignored_dirs,
+    )?;
+    if options.only_changed {
+        let changed = changed_files(directory)?;
+        matched.retain(|file| {
+            let is_changed = fs::canonicalize(&file.path).is_ok_and(|path| changed.contains(&path));
+            if !is_changed {
+                log_skip(&file.display_path, &SkipReason::Unchanged);
+            }
+            is_changed
+        });
+    }
+    matched.retain(|file| {
+        let canonical = fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone());
+        let is_new = seen.insert(canonical);
+        if !is_new {
+            log_skip(&file.display_path, &SkipReason::Duplicate);
+        }
+        is_new
+    });
+    if options.exclude_empty {
+        matched = skip_empty(matched, stats);
+    }
+    if let Some(command) = &options.filter_command {
+        matched = filter_with_command(matched, command)?;
+    }
+    if options.sort != SortOrder::Path || options.reverse {
+        sort_files(&mut matched, options.sort, options.reverse);
+    }
+    if options.interactive {
+        matched = select_files(matched)?;
+    }
+    if options.with_git_info {
+        annotate_commits(directory, &mut matched);
+    }
+    for file in &matched {
+        stats.record(file);
+    }
+    Ok(matched)
+}
+
+/// Lists the files for `--append-file` and `--append-dir`: each named file, then every file
+/// under each directory, sorted by path. They are named as given, and no search filter
+/// applies to them.
+pub fn collect_appended_files(
+    files: &[PathBuf],
+    dirs: &[PathBuf],
+) -> Result<Vec<MatchedFile>, ContextError> {
+    let appended = |path: PathBuf| -> Result<MatchedFile, ContextError> {
+        let metadata = fs::metadata(&path).map_err(|e| {
+            io::Error::new(e.kind(), format!("Cannot append {}: {}", path.display(), e))
+        })?;
+        if !metadata.is_file() {
+            return Err(ContextError::NotAFile(path));
+        }
+        Ok(MatchedFile {
+            display_path: path.clone(),
+            path,
+            size: metadata.len(),
+            language: None,
+            extraction: None,
+            commit: None,
+        })
+    };
+
+    let mut appended_files = Vec::new();
+    for file in files {
+        appended_files.push(appended(file.clone())?);
+    }
+    for dir in dirs {
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Cannot append {}: not a directory", dir.display()),
+            )
+            .into());
+        }
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.file_type().is_file() {
+                appended_files.push(appended(entry.into_path())?);
+            }
+        }
+    }
+    Ok(appended_files)
+}
+
+/// Reads newline-separated paths for `--files-from` from a file, or stdin for `-`, keeping
+/// their order. Paths that can't be read are logged and skipped.
+pub fn read_file_list(source: &Path) -> Result<Vec<MatchedFile>, ContextError> {
+    let list = if source == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(source).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read file list {}: {}", source.display(), e),
+            )
+        })?
+    };
+
+    let mut files = Vec::new();
+    for line in list.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let path = PathBuf::from(line);
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => files.push(MatchedFile {
+                display_path: path.clone(),
+                path,
+                size: metadata.len(),
+                language: None,
+                extraction: None,
+                commit: None,
+            }),
+            Ok(_) => warn!(
+                event = "skipped", path:% = path.display(), reason = "not_a_file";
+                "Skipping {}: not a file", path.display()
+            ),
+            Err(e) => error!(
+                event = "skipped", path:% = path.display(), reason = "read_error";
+                "Error reading metadata for {}: {}", path.display(), e
+            ),
+        }
+    }
+    Ok(files)
+}
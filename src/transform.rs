@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::error::ContextError;
+
+/// Applies the `transforms` entry from `.llmcontext.toml` for the extension `ext` (matched
+/// without its dot and case-insensitively) to a file's contents. Contents with no entry for
+/// their extension are returned unchanged.
+///
+/// An entry is either a built-in (`pretty-json` re-indents JSON, `collapse` replaces the
+/// contents with a one-line size note) or a shell command such as `jq .`, which receives the
+/// contents on stdin and whose stdout replaces them. A command that fails is an error, with
+/// its stderr in the message.
+pub fn apply_transform(
+    ext: &str,
+    contents: &str,
+    transforms: &BTreeMap<String, String>,
+) -> Result<String, ContextError> {
+    let ext = ext.trim_start_matches('.').to_lowercase();
+    match transforms.get(&ext) {
+        Some(transform) => run_transform(transform, contents),
+        None => Ok(contents.to_string()),
+    }
+}
+
+fn run_transform(transform: &str, contents: &str) -> Result<String, ContextError> {
+    match transform {
+        "pretty-json" => {
+            let value: serde_json::Value = serde_json::from_str(contents)?;
+            Ok(serde_json::to_string_pretty(&value)? + "\n")
+        }
+        "collapse" => Ok(format!(
+            "... (collapsed {} bytes, {} lines)\n",
+            contents.len(),
+            contents.lines().count()
+        )),
+        command => run_command(command, contents),
+    }
+}
+
+/// Runs `command` through the shell with `contents` on stdin, returning its stdout. A command
+/// that fails is an error, with its stderr in the message.
+pub(crate) fn run_command(command: &str, contents: &str) -> Result<String, ContextError> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| ContextError::CommandNotRun {
+            command: command.to_string(),
+            source,
+        })?;
+
+    // Written from another thread so a command that streams its output can't deadlock on a
+    // full pipe while its input is still being written
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = contents.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    // A command may exit without reading all of its input, which only matters if it failed
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(ContextError::CommandFailed {
+            command: command.to_string(),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
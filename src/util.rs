@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Parses a duration such as `90s`, `15m`, `3h`, `2d`, or `1w`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}'", s))?;
+    let seconds = match unit.to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid duration '{}': expected a unit of s, m, h, d, or w",
+                s
+            ))
+        }
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Duration '{}' is too large", s))
+}
+
+/// Expands `~` and `$VAR` / `${VAR}` in a path argument. Unset variables are an error rather
+/// than silently becoming empty.
+pub fn expand_path(s: &str) -> Result<PathBuf, String> {
+    expand_vars(s).map(PathBuf::from)
+}
+
+/// Expands `~` and environment variables in a non-glob argument such as a directory name.
+pub fn expand_vars(s: &str) -> Result<String, String> {
+    shellexpand::full(s)
+        .map(|expanded| expanded.into_owned())
+        .map_err(|e| format!("Cannot expand '{}': {}", s, e))
+}
+
+/// Expands only a leading `~` in a glob, leaving `$` and everything else to the glob syntax.
+pub fn expand_tilde(s: &str) -> Result<String, String> {
+    Ok(shellexpand::tilde(s).into_owned())
+}
+
+/// Parses a byte size such as `4096`, `500k`, `2M`, or `1.5GB` (binary multiples).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size '{}'", s))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1024,
+        "m" | "mb" => 1024 * 1024,
+        "g" | "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size suffix '{}' in '{}'", suffix, s)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Formats a byte count for humans, e.g. `512B`, `4.2KB`, `340KB`, `1.2MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{:.1}{}", value, UNITS[unit])
+    } else {
+        format!("{:.0}{}", value, UNITS[unit])
+    }
+}
+
+/// Context window sizes, in tokens, for models accepted by `--model`.
+pub const MODEL_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4o-mini", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4", 8_192),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3-mini", 200_000),
+    ("claude-3-haiku", 200_000),
+    ("claude-3-opus", 200_000),
+    ("claude-3-5-sonnet", 200_000),
+    ("claude-3-7-sonnet", 200_000),
+    ("gemini-1.5-flash", 1_048_576),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini-2.0-flash", 1_048_576),
+];
+
+pub fn context_window(model: &str) -> Option<usize> {
+    MODEL_CONTEXT_WINDOWS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(model))
+        .map(|(_, window)| *window)
+}
+
+/// Formats a token count compactly, e.g. `145k`.
+pub fn format_tokens(tokens: usize) -> String {
+    if tokens < 1000 {
+        tokens.to_string()
+    } else {
+        format!("{}k", (tokens + 500) / 1000)
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::{Cursor, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use llm_context_builder::{extract_archive, ArchiveFormat, ContextError};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+fn zip_with(entries: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in entries {
+        writer
+            .start_file(*name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+    }
+    Cursor::new(writer.finish().unwrap().into_inner())
+}
+
+/// Builds a tarball by hand, since `tar::Builder` refuses to write `..` paths.
+fn tar_gz_with(name: &str, contents: &str) -> Cursor<Vec<u8>> {
+    let mut header = tar::Header::new_gnu();
+    header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    builder.append(&header, contents.as_bytes()).unwrap();
+    Cursor::new(builder.into_inner().unwrap().finish().unwrap())
+}
+
+#[test]
+fn extracts_into_the_wrapping_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let archive = zip_with(&[("repo-main/src/lib.rs", "pub fn f() {}")]);
+
+    let root = extract_archive(archive, ArchiveFormat::Zip, &target, 1).unwrap();
+
+    assert_eq!(root, target.join("repo-main"));
+    assert!(root.join("src/lib.rs").is_file());
+}
+
+#[test]
+fn extracts_zip_entries_in_parallel_like_serially() {
+    let dir = tempfile::tempdir().unwrap();
+    let names: Vec<_> = (0..64)
+        .map(|i| format!("repo-main/src/m{}/f{}.rs", i % 8, i))
+        .collect();
+    let mut entries: Vec<_> = names
+        .iter()
+        .map(|name| (name.as_str(), name.as_str()))
+        .collect();
+    // The last of two entries with the same path wins either way
+    entries.push(("repo-main/src/m0/./f0.rs", "replaced"));
+
+    for jobs in [1, 4] {
+        let target = dir.path().join(jobs.to_string());
+        let root = extract_archive(zip_with(&entries), ArchiveFormat::Zip, &target, jobs).unwrap();
+
+        assert_eq!(root, target.join("repo-main"));
+        for name in &names[1..] {
+            assert_eq!(std::fs::read_to_string(target.join(name)).unwrap(), *name);
+        }
+        let replaced = std::fs::read_to_string(root.join("src/m0/f0.rs")).unwrap();
+        assert_eq!(replaced, "replaced");
+    }
+}
+
+#[test]
+fn rejects_zip_entries_that_escape_the_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let archive = zip_with(&[
+        ("repo-main/README.md", "hello"),
+        ("repo-main/../../evil.txt", "pwned"),
+    ]);
+
+    let err = extract_archive(archive, ArchiveFormat::Zip, &target, 1).unwrap_err();
+
+    assert!(matches!(err, ContextError::Extraction(_)), "{:?}", err);
+    assert!(err.to_string().contains("outside"), "{}", err);
+    assert!(!dir.path().join("evil.txt").exists());
+}
+
+#[test]
+fn rejects_absolute_zip_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let escaped = dir.path().join("evil.txt");
+    let archive = zip_with(&[(escaped.to_str().unwrap(), "pwned")]);
+
+    assert!(extract_archive(archive, ArchiveFormat::Zip, &target, 1).is_err());
+    assert!(!escaped.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_zip_entries_written_through_a_symlink() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let outside = dir.path().join("outside");
+    std::fs::create_dir_all(&target).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, target.join("link")).unwrap();
+    let archive = zip_with(&[("link/evil.txt", "pwned")]);
+
+    assert!(extract_archive(archive, ArchiveFormat::Zip, &target, 1).is_err());
+    assert!(!outside.join("evil.txt").exists());
+}
+
+#[test]
+fn rejects_tar_entries_that_escape_the_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let archive = tar_gz_with("../evil.txt", "pwned");
+
+    let err = extract_archive(archive, ArchiveFormat::TarGz, &target, 1).unwrap_err();
+
+    assert!(err.to_string().contains("outside"), "{}", err);
+    assert!(!dir.path().join("evil.txt").exists());
+}
+
+#[cfg(windows)]
+#[test]
+fn extracts_zip_entries_longer_than_max_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let nested = vec!["node_modules"; 30].join("/");
+    let name = format!("repo-main/{}/index.js", nested);
+    let archive = zip_with(&[(name.as_str(), "module.exports = {}")]);
+
+    let root = extract_archive(archive, ArchiveFormat::Zip, &target, 1).unwrap();
+
+    let extracted = root.join(nested.replace('/', "\\")).join("index.js");
+    assert!(extracted.as_os_str().len() > 260);
+    assert_eq!(
+        std::fs::read_to_string(extracted).unwrap(),
+        "module.exports = {}"
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn skips_zip_entries_with_reserved_windows_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let archive = zip_with(&[
+        ("repo-main/aux.js", "reserved"),
+        ("repo-main/CON/index.js", "reserved"),
+        ("repo-main/src/lib.rs", "pub fn f() {}"),
+    ]);
+
+    let root = extract_archive(archive, ArchiveFormat::Zip, &target, 1).unwrap();
+
+    let names: Vec<_> = std::fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["src"]);
+    assert!(root.join("src").join("lib.rs").is_file());
+}
+
+#[cfg(windows)]
+#[test]
+fn skips_tar_entries_with_reserved_windows_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    let archive = tar_gz_with("repo-main/nul.txt", "reserved");
+
+    extract_archive(archive, ArchiveFormat::TarGz, &target, 1).unwrap();
+
+    assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+}
@@ -0,0 +1,22 @@
+use std::fs;
+
+use llm_context_builder::is_probably_binary;
+
+#[test]
+fn nul_bytes_and_invalid_utf8_are_binary() {
+    let dir = tempfile::tempdir().unwrap();
+    let cases: [(&str, &[u8], bool); 3] = [
+        (
+            "text.rs",
+            "fn main() { println!(\"héllo\"); }".as_bytes(),
+            false,
+        ),
+        ("image.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", true),
+        ("latin1.txt", b"caf\xe9 cr\xe8me", true),
+    ];
+    for (name, contents, binary) in cases {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        assert_eq!(is_probably_binary(&path).unwrap(), binary, "{}", name);
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use llm_context_builder::{find_files, SearchOptions, Stats};
+
+#[test]
+fn skips_files_already_seen_and_records_stubbed_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("vendor")).unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(root.join("src/lib.rs"), "pub fn f() {}").unwrap();
+    fs::write(root.join("vendor/dep.rs"), "pub fn dep() {}").unwrap();
+    let options = SearchOptions {
+        extensions: vec!["rs".to_string()],
+        ignored_dirs: vec!["vendor".to_string()],
+        stub_ignored: true,
+        ..Default::default()
+    };
+    let mut seen = HashSet::from([fs::canonicalize(root.join("src/lib.rs")).unwrap()]);
+    let mut stats = Stats::default();
+
+    let files = find_files(root, root, &options, &mut seen, &mut stats).unwrap();
+
+    let paths: Vec<_> = files.iter().map(|file| file.display_path.clone()).collect();
+    assert_eq!(paths, [PathBuf::from("src/main.rs")]);
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.ignored_dirs, [(PathBuf::from("vendor"), 1)]);
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::path::Path;
+
+use git2::{Repository, Signature};
+use llm_context_builder::{ContextBuilder, ContextError, OutputFormat, SearchOptions};
+
+fn options() -> SearchOptions {
+    SearchOptions {
+        extensions: vec!["rs".to_string()],
+        print_contents: true,
+        only_changed: true,
+        format: OutputFormat::Json,
+        ..Default::default()
+    }
+}
+
+fn commit_all(repo: &Repository, files: &[&str]) {
+    let mut index = repo.index().unwrap();
+    for file in files {
+        index.add_path(Path::new(file)).unwrap();
+    }
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+        .unwrap();
+}
+
+#[test]
+fn lists_modified_staged_and_untracked_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let repo = Repository::init(root).unwrap();
+    fs::create_dir_all(root.join("src/nested")).unwrap();
+    for file in ["src/clean.rs", "src/modified.rs"] {
+        fs::write(root.join(file), "fn main() {}").unwrap();
+    }
+    commit_all(&repo, &["src/clean.rs", "src/modified.rs"]);
+    fs::write(root.join("src/modified.rs"), "fn main() { changed() }").unwrap();
+    fs::write(root.join("src/staged.rs"), "fn staged() {}").unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("src/staged.rs")).unwrap();
+    index.write().unwrap();
+    fs::write(root.join("src/nested/untracked.rs"), "fn new() {}").unwrap();
+    fs::write(root.join(".gitignore"), "ignored.rs\n").unwrap();
+    fs::write(root.join("src/ignored.rs"), "fn ignored() {}").unwrap();
+
+    let document = ContextBuilder::new(options())
+        .path(root.join("src"))
+        .build()
+        .unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&document).unwrap();
+    let mut paths: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["path"].as_str().unwrap().to_string())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["modified.rs", "nested/untracked.rs", "staged.rs"]);
+}
+
+#[test]
+fn a_directory_outside_a_work_tree_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+
+    let err = ContextBuilder::new(options())
+        .path(dir.path())
+        .build()
+        .unwrap_err();
+
+    assert!(
+        matches!(&err, ContextError::NotAWorkTree(path) if path == dir.path()),
+        "{:?}",
+        err
+    );
+}
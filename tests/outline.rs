@@ -0,0 +1,125 @@
+use llm_context_builder::outline;
+
+#[test]
+fn rust_keeps_item_members_and_elides_function_bodies() {
+    let source = r#"
+pub struct Point {
+    pub x: i32,
+}
+
+impl Point {
+    /// Builds a point.
+    pub fn new(x: i32) -> Self {
+        let message = "}";
+        Point { x }
+    }
+}
+
+trait Shape {
+    fn area(&self) -> f64;
+    fn describe(&self) -> String {
+        format!("{}", self.area())
+    }
+}
+"#;
+    assert_eq!(
+        outline(source, "rust"),
+        r#"
+pub struct Point {
+    pub x: i32,
+}
+
+impl Point {
+    pub fn new(x: i32) -> Self { ... }
+}
+
+trait Shape {
+    fn area(&self) -> f64;
+    fn describe(&self) -> String { ... }
+}
+"#
+    );
+}
+
+#[test]
+fn rust_keeps_struct_literals_in_constants() {
+    let source = "const ORIGIN: Point = Point { x: 0, y: 0 };\nfn main() {\n    run();\n}\n";
+    assert_eq!(
+        outline(source, "rust"),
+        "const ORIGIN: Point = Point { x: 0, y: 0 };\nfn main() { ... }\n"
+    );
+}
+
+#[test]
+fn cpp_class_inside_a_function_is_elided_with_the_body() {
+    let source = r#"namespace geometry {
+class Shape {
+public:
+    virtual double area() const = 0;
+    int sides() const { return 0; }
+};
+}
+
+int main() {
+    class Local {
+        void run() {}
+    };
+    return 0;
+}
+"#;
+    assert_eq!(
+        outline(source, "cpp"),
+        r#"namespace geometry {
+class Shape {
+public:
+    virtual double area() const = 0;
+    int sides() const { ... }
+};
+}
+
+int main() { ... }
+"#
+    );
+}
+
+#[test]
+fn python_keeps_class_members_and_elides_function_bodies() {
+    let source = r#"class Greeter:
+    greeting = "hello"
+
+    def greet(self, name):
+        if name:
+            return f"{self.greeting} {name}"
+        return self.greeting
+
+def main():
+    Greeter().greet("world")
+"#;
+    assert_eq!(
+        outline(source, "python"),
+        r#"class Greeter:
+    greeting = "hello"
+
+    def greet(self, name):
+        ...
+
+def main():
+    ...
+"#
+    );
+}
+
+#[test]
+fn typescript_elides_arrow_function_blocks_but_not_expressions() {
+    let source = "export const double = (x: number) => x * 2;\nexport const log = (x: string) => {\n    console.log(x);\n};\n";
+    assert_eq!(
+        outline(source, "typescript"),
+        "export const double = (x: number) => x * 2;\nexport const log = (x: string) => { ... };\n"
+    );
+}
+
+#[test]
+fn languages_without_a_grammar_are_unchanged() {
+    let source = "fun main() {\n    println(\"hi\")\n}\n";
+    assert_eq!(outline(source, "kotlin"), source);
+}
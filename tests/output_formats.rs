@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::Path;
+
+use llm_context_builder::{ContextBuilder, OutputFormat, SearchOptions};
+
+fn source_dir(root: &Path, name: &str, file: &str, contents: &str) -> std::path::PathBuf {
+    let dir = root.join(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(file), contents).unwrap();
+    dir
+}
+
+fn options(format: OutputFormat) -> SearchOptions {
+    SearchOptions {
+        extensions: vec!["rs".to_string()],
+        print_contents: true,
+        format,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn xml_for_several_paths_has_a_single_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = source_dir(dir.path(), "backend", "main.rs", "fn main() {}");
+    let frontend = source_dir(
+        dir.path(),
+        "frontend",
+        "lib.rs",
+        "pub fn f() -> bool { 1 < 2 }",
+    );
+
+    let document = ContextBuilder::new(options(OutputFormat::Xml))
+        .path(&backend)
+        .path(&frontend)
+        .preamble("Review <this>")
+        .build()
+        .unwrap();
+
+    let xml = roxmltree::Document::parse(&document).unwrap();
+    let root = xml.root_element();
+    assert_eq!(root.tag_name().name(), "documents");
+    let children: Vec<_> = root.children().filter(|node| node.is_element()).collect();
+    let names: Vec<_> = children.iter().map(|node| node.tag_name().name()).collect();
+    assert_eq!(names, ["preamble", "root", "root"]);
+    assert_eq!(
+        children[1].attribute("path"),
+        Some(&*backend.display().to_string())
+    );
+    let file = children[2]
+        .children()
+        .find(|node| node.has_tag_name("file"))
+        .unwrap();
+    assert_eq!(file.attribute("path"), Some("lib.rs"));
+    assert_eq!(file.text().unwrap().trim(), "pub fn f() -> bool { 1 < 2 }");
+}
+
+#[test]
+fn xml_replaces_control_characters_it_cannot_represent() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = source_dir(
+        dir.path(),
+        "logs",
+        "colors.rs",
+        "// \u{1b}[31mred\u{1b}[0m\n\u{c}\tdone\r\n",
+    );
+
+    let document = ContextBuilder::new(options(OutputFormat::Xml))
+        .path(&source)
+        .build()
+        .unwrap();
+
+    let xml = roxmltree::Document::parse(&document).unwrap();
+    let file = xml
+        .descendants()
+        .find(|node| node.has_tag_name("file"))
+        .unwrap();
+    assert_eq!(
+        file.text().unwrap().trim(),
+        "// \u{fffd}[31mred\u{fffd}[0m\n\u{fffd}\tdone"
+    );
+}
+
+#[test]
+fn json_for_several_paths_and_appended_files_is_a_single_array() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = source_dir(dir.path(), "backend", "main.rs", "fn main() {}");
+    let frontend = source_dir(dir.path(), "frontend", "lib.rs", "pub fn f() {}");
+    let instructions = dir.path().join("instructions.md");
+    fs::write(&instructions, "Explain the code").unwrap();
+    let appended = llm_context_builder::collect_appended_files(&[instructions], &[]).unwrap();
+
+    let document = ContextBuilder::new(options(OutputFormat::Json))
+        .labeled_source("backend", &backend, &backend)
+        .labeled_source("frontend", &frontend, &frontend)
+        .append_files(appended)
+        .preamble("Review this")
+        .build()
+        .unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&document).unwrap();
+    assert_eq!(json["preamble"], "Review this");
+    let files = json["files"].as_array().unwrap();
+    let entries: Vec<_> = files
+        .iter()
+        .map(|file| (file["root"].as_str(), file["path"].as_str().unwrap()))
+        .collect();
+    assert_eq!(entries[0], (Some("backend"), "main.rs"));
+    assert_eq!(entries[1], (Some("frontend"), "lib.rs"));
+    assert_eq!(entries[2].0, None);
+    assert_eq!(files[2]["contents"], "Explain the code");
+}
+
+#[test]
+fn xml_with_token_counts_keeps_the_summary_out_of_the_document() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = source_dir(dir.path(), "src", "main.rs", "// hello\nfn main() {}");
+
+    let document = ContextBuilder::new(SearchOptions {
+        count_tokens: true,
+        strip_comments: true,
+        max_tokens: Some(1000),
+        ..options(OutputFormat::Xml)
+    })
+    .path(&src)
+    .build()
+    .unwrap();
+
+    let xml = roxmltree::Document::parse(&document).unwrap();
+    assert_eq!(xml.root_element().tag_name().name(), "documents");
+    assert!(!document.contains("Total:"), "{}", document);
+}
+
+#[test]
+fn split_output_and_bundle_apply_the_same_transforms_as_printed_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = source_dir(dir.path(), "src", "data.json", r#"{"a":1}"#);
+    let split = dir.path().join("split");
+    let bundle = dir.path().join("bundle.zip");
+    let options = SearchOptions {
+        extensions: vec!["json".to_string()],
+        transforms: [("json".to_string(), "pretty-json".to_string())].into(),
+        ..options(OutputFormat::Json)
+    };
+
+    let document = ContextBuilder::new(options)
+        .path(&src)
+        .split_output(&split)
+        .bundle(&bundle)
+        .build()
+        .unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&document).unwrap();
+    let printed = json[0]["contents"].as_str().unwrap();
+    assert_eq!(
+        fs::read_to_string(split.join("data.json")).unwrap(),
+        printed
+    );
+    let mut archive = zip::ZipArchive::new(fs::File::open(&bundle).unwrap()).unwrap();
+    let bundled = std::io::read_to_string(archive.by_name("data.json").unwrap()).unwrap();
+    assert_eq!(bundled, printed);
+    assert!(printed.contains("\n  \"a\": 1"), "{}", printed);
+}
@@ -0,0 +1,20 @@
+use llm_context_builder::{parse_github_url, ContextError, RepoHost};
+
+#[test]
+fn parses_a_tree_url_into_its_branch_and_folder() {
+    let info = parse_github_url("https://github.com/owner/repo/tree/main/src/lib").unwrap();
+    assert_eq!(info.host, RepoHost::GitHub);
+    assert_eq!(info.repo_url, "https://github.com/owner/repo");
+    assert_eq!(info.branch_name.as_deref(), Some("main"));
+    assert_eq!(info.folder_path.as_deref(), Some("src/lib"));
+}
+
+#[test]
+fn rejects_enterprise_hosts_it_was_not_told_about() {
+    let err = parse_github_url("https://github.mycorp.com/owner/repo").unwrap_err();
+    assert!(
+        matches!(&err, ContextError::NotAGithubUrl(host) if host == "github.mycorp.com"),
+        "{:?}",
+        err
+    );
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use llm_context_builder::{apply_transform, ContextError};
+
+fn transforms(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+    entries
+        .iter()
+        .map(|(ext, transform)| (ext.to_string(), transform.to_string()))
+        .collect()
+}
+
+#[test]
+fn looks_up_the_transform_by_extension() {
+    let transforms = transforms(&[("json", "pretty-json")]);
+    assert_eq!(
+        apply_transform(".JSON", r#"{"a":1}"#, &transforms).unwrap(),
+        "{\n  \"a\": 1\n}\n"
+    );
+    assert_eq!(
+        apply_transform("txt", r#"{"a":1}"#, &transforms).unwrap(),
+        r#"{"a":1}"#
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn commands_get_the_contents_on_stdin() {
+    let transforms = transforms(&[("txt", "tr a-z A-Z"), ("log", "echo broken >&2; exit 3")]);
+    assert_eq!(
+        apply_transform("txt", "hello", &transforms).unwrap(),
+        "HELLO"
+    );
+    let err = apply_transform("log", "hello", &transforms).unwrap_err();
+    assert!(
+        matches!(&err, ContextError::CommandFailed { stderr, .. } if stderr.trim() == "broken"),
+        "{:?}",
+        err
+    );
+}